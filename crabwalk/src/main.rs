@@ -1,12 +1,16 @@
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
-use walkingpad_btle::{WalkingPadReceiver, WalkingPadSender};
-use walkingpad_protocol::request;
-use walkingpad_protocol::response::{MotorState, Response, State, StoredStats};
-use walkingpad_protocol::{Mode, Units};
+use walkingpad_btle::{
+    ConnectOptions, ConnectionEvent, PollingOptions, ReconnectPolicy, RunId, SyncOptions,
+    SyncReport, Target, Treadmill,
+};
+use walkingpad_protocol::response::{MotorState, State, StoredStats};
+use walkingpad_protocol::{Mode, Response, Units};
 
 use chrono::{DateTime, Local};
 use simplelog::*;
@@ -21,6 +25,22 @@ struct RunStats {
     distance: u32,
 
     nb_steps: u32,
+
+    /// The start time on the treadmill's internal clock, for the runs from its history, which
+    /// tells them apart from the runs saved by a previous sync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stored_start_time: Option<u32>,
+}
+
+impl RunStats {
+    fn run_id(&self) -> Option<RunId> {
+        Some(RunId {
+            start_time: self.stored_start_time?,
+            duration: self.duration,
+            distance: self.distance,
+            nb_steps: self.nb_steps,
+        })
+    }
 }
 
 impl From<StoredStats> for RunStats {
@@ -33,6 +53,7 @@ impl From<StoredStats> for RunStats {
             duration: stats.duration,
             distance: stats.distance,
             nb_steps: stats.nb_steps,
+            stored_start_time: Some(stats.start_time),
         }
     }
 }
@@ -49,74 +70,179 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let (sender, receiver) = connect_with_retry()?;
+    // e.g. `crabwalk sim://` or `crabwalk --capture pad.jsonl tcp://host:4242`, any treadmill
+    // in range otherwise
+    let mut options = ConnectOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capture" => {
+                options.capture = Some(args.next().ok_or("missing capture path")?.into())
+            }
+            uri => options.target = uri.parse()?,
+        }
+    }
+    // Meant to run unattended, so the link is recovered whenever it drops, unless it's a replay
+    // which is over
+    if !matches!(options.target, Target::Replay { .. }) {
+        options.reconnect = Some(ReconnectPolicy::default());
+    }
+    let reconnects = options.reconnect.is_some();
+    options.polling = Some(PollingOptions::default());
+    let treadmill: Arc<dyn Treadmill> = connect_with_retry(options)?.into();
 
-    sender.send(request::set::mode(Mode::Manual))?;
-    sender.send(request::set::units(Units::Metric))?;
+    for result in [
+        treadmill.set_mode(Mode::Manual),
+        treadmill.set_units(Units::Metric),
+    ] {
+        match result {
+            Ok(()) | Err(walkingpad_btle::Error::Unsupported) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
 
     let mut stats_file = File::options()
         .create(true)
+        .read(true)
         .append(true)
         .open("stats.json")?;
+    // The runs saved last time are still on the treadmill if clearing them failed
+    let mut sync_options = SyncOptions {
+        synced: saved_runs(&stats_file)?,
+        ..SyncOptions::default()
+    };
 
-    let (stats, err) = walkingpad_btle::gather_run_statistics(&sender, &receiver);
+    let report = save_history(&*treadmill, &mut sync_options, &mut stats_file);
 
-    for stats in stats.into_iter().map(RunStats::from) {
-        if let Err(err) = serde_json::to_string(&stats).map(|s| writeln!(stats_file, "{}", s)) {
-            log::error!("unable to save stored statistics: {}", err);
-        }
-    }
-
-    if let Some(err) = err {
-        return Err(err.into());
+    match report.error {
+        None | Some(walkingpad_btle::Error::Unsupported) => {}
+        Some(err) => return Err(err.into()),
     }
 
     {
-        let sender = sender.clone();
+        let treadmill = treadmill.clone();
+        let received = treadmill.received();
 
         std::thread::spawn(move || {
             let mut app_state: Option<(State, SystemTime)> = None;
 
-            while let Ok(response) = receiver.recv() {
-                match response {
-                    Response::State(state) => {
-                        handle_state_update(&mut app_state, state, &mut stats_file, &sender);
+            while let Ok(received) = received.recv() {
+                match received.item {
+                    Ok(Response::State(state)) => handle_state_update(
+                        &mut app_state,
+                        state,
+                        received.time,
+                        &mut sync_options,
+                        &mut stats_file,
+                        &*treadmill,
+                    ),
+                    Ok(_) => {}
+                    Err(malformed) => {
+                        log::error!("{}: {:02x?}", malformed.reason, malformed.frame.data)
                     }
-                    _ => log::info!("{}", response),
                 }
             }
         });
     }
 
-    sender.send(request::get::settings())?;
+    match treadmill.settings() {
+        Ok(settings) => log::info!("{}", settings),
+        Err(walkingpad_btle::Error::Unsupported) => {}
+        Err(err) => return Err(err.into()),
+    }
 
-    loop {
-        sender.send(request::get::state())?;
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    // The state is polled by the connection, only its events are left to watch
+    let events = treadmill.connection_events();
+    while let Ok(event) = events.recv() {
+        match event {
+            ConnectionEvent::Connected => log::info!("Connected to the WalkingPad"),
+            ConnectionEvent::Disconnected => {
+                log::warn!("Disconnected from the WalkingPad");
+                // The replay is over
+                if !reconnects {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Err(walkingpad_btle::Error::ConnectionClosed.into())
+}
+
+/// Reads back the runs from the treadmill's history already in the stats file.
+fn saved_runs(stats_file: &File) -> Result<HashSet<RunId>, Box<dyn std::error::Error>> {
+    let mut runs = HashSet::new();
+    for line in BufReader::new(stats_file).lines() {
+        let stats: RunStats = serde_json::from_str(&line?)?;
+        runs.extend(stats.run_id());
+    }
+
+    Ok(runs)
+}
+
+/// Appends the runs stored on the treadmill which weren't saved yet to the stats file, and
+/// clears them from the treadmill once they are safely on disk.
+fn save_history(
+    treadmill: &dyn Treadmill,
+    options: &mut SyncOptions,
+    stats_file: &mut File,
+) -> SyncReport {
+    let report = treadmill.sync_history(options, &mut |progress| {
+        log::info!("Fetched {} stored runs", progress.fetched)
+    });
+
+    let saved = report
+        .records
+        .iter()
+        .cloned()
+        .map(RunStats::from)
+        .try_for_each(|stats| append_stats(stats_file, &stats))
+        .and_then(|_| Ok(stats_file.sync_data()?));
+
+    match saved {
+        Err(err) => log::error!("unable to save stored statistics: {}", err),
+        Ok(()) => {
+            // Not to save them again, should they stay on the treadmill
+            options
+                .synced
+                .extend(report.records.iter().map(RunId::from));
+            if report.is_complete() {
+                if let Err(err) = treadmill.clear_history(&report) {
+                    log::warn!("stored statistics not cleared: {}", err);
+                }
+            }
+        }
     }
+
+    report
+}
+
+fn append_stats(stats_file: &mut File, stats: &RunStats) -> Result<(), Box<dyn std::error::Error>> {
+    writeln!(stats_file, "{}", serde_json::to_string(stats)?)?;
+    Ok(())
 }
 
-fn connect_with_retry() -> walkingpad_btle::Result<(WalkingPadSender, WalkingPadReceiver)> {
+fn connect_with_retry(options: ConnectOptions) -> walkingpad_btle::Result<Box<dyn Treadmill>> {
     let mut retry_count = 0;
     loop {
-        let result = walkingpad_btle::connect();
-        if let Err(walkingpad_btle::Error::NoWalkingPadFound) = result {
-            if retry_count > 3 {
-                return result;
+        match walkingpad_btle::connect_treadmill_with(options.clone()) {
+            Err(err) if err.is_retryable() && retry_count <= 3 => {
+                retry_count += 1;
+                log::info!("{}, retrying", err);
+                std::thread::sleep(Duration::from_secs(1));
             }
-            retry_count += 1;
-            log::info!("No WalkingPad found, retrying");
-            continue;
+            result => return result,
         }
-        return result;
     }
 }
 
 fn handle_state_update(
     app_state: &mut Option<(State, SystemTime)>,
     state: State,
+    received_at: SystemTime,
+    sync_options: &mut SyncOptions,
     stats_file: &mut File,
-    sender: &WalkingPadSender,
+    treadmill: &dyn Treadmill,
 ) {
     if let Some((last_state, start_time)) = app_state.as_mut() {
         if state.motor_state == MotorState::Running {
@@ -128,16 +254,27 @@ fn handle_state_update(
                 duration: last_state.run_time,
                 distance: last_state.distance,
                 nb_steps: last_state.nb_steps,
+                stored_start_time: None,
             };
-
-            writeln!(stats_file, "{}", serde_json::to_string(&stats).unwrap()).unwrap();
-            let _ = stats_file.flush();
             *app_state = None;
             log::info!("Run finished!");
-            let _ = sender.send(request::clear_stats());
+
+            // The treadmill keeps the run, unless it has no history to keep it in
+            let report = save_history(treadmill, sync_options, stats_file);
+            match report.error {
+                None => {}
+                Some(walkingpad_btle::Error::Unsupported) => {
+                    let saved =
+                        append_stats(stats_file, &stats).and_then(|_| Ok(stats_file.sync_data()?));
+                    if let Err(err) = saved {
+                        log::error!("unable to save the run statistics: {}", err);
+                    }
+                }
+                Some(err) => log::error!("unable to sync the stored statistics: {}", err),
+            }
         }
     } else if state.motor_state == MotorState::Running {
         log::info!("Run started!");
-        *app_state = Some((state, SystemTime::now()));
+        *app_state = Some((state, received_at));
     }
 }
@@ -0,0 +1,163 @@
+//! The Bluetooth Low Energy transport, through btleplug.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use btleplug::api::Peripheral as _;
+use btleplug::api::{Characteristic, WriteType};
+use btleplug::platform::Peripheral;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, StreamExt};
+
+use crate::client::Backend;
+use crate::discovery::BDAddr;
+use crate::transport::{Frame, FrameStream, Transport};
+use crate::{ConnectionEvent, Error, Result};
+
+/// The link's state isn't notified by every platform, so it gets polled.
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connected peripheral, subscribed to the notify characteristics of its protocol.
+///
+/// The peripheral is disconnected once the transport is dropped.
+pub struct BleTransport {
+    peripheral: Peripheral,
+    write_characteristic: Characteristic,
+    write_type: WriteType,
+    is_disconnected: AtomicBool,
+}
+
+impl BleTransport {
+    /// Connects to the peripheral and subscribes to the characteristics the backend relies on.
+    pub(crate) async fn connect(
+        peripheral: Peripheral,
+        backend: Backend,
+        write_type: WriteType,
+    ) -> Result<BleTransport> {
+        peripheral.connect().await?;
+
+        // Nothing else would disconnect the peripheral once this returns
+        let write_characteristic = match subscribe(&peripheral, backend).await {
+            Ok(write_characteristic) => write_characteristic,
+            Err(err) => {
+                if let Err(err) = peripheral.disconnect().await {
+                    log::warn!("WalkingPad disconnection failed: {}", err);
+                }
+                return Err(err);
+            }
+        };
+
+        Ok(BleTransport {
+            peripheral,
+            write_characteristic,
+            write_type,
+            is_disconnected: AtomicBool::new(false),
+        })
+    }
+
+    pub fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+}
+
+/// Returns the characteristic to write the backend's requests to.
+async fn subscribe(peripheral: &Peripheral, backend: Backend) -> Result<Characteristic> {
+    peripheral.discover_services().await?;
+
+    let characteristics = peripheral.characteristics();
+
+    let write_characteristic = characteristics
+        .iter()
+        .find(|c| c.uuid == backend.write_characteristic_uuid())
+        .ok_or(Error::CharacteristicMissing {
+            uuid: backend.write_characteristic_uuid(),
+        })?
+        .clone();
+
+    for uuid in backend.notify_characteristic_uuids() {
+        let read_characteristic = characteristics
+            .iter()
+            .find(|c| c.uuid == *uuid)
+            .ok_or(Error::CharacteristicMissing { uuid: *uuid })?;
+
+        peripheral.subscribe(read_characteristic).await?;
+    }
+
+    Ok(write_characteristic)
+}
+
+impl Transport for BleTransport {
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.peripheral
+                .write(&self.write_characteristic, frame, self.write_type)
+                .await
+                .map_err(|err| Error::WriteFailed(err.into()))
+        }
+        .boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        async move {
+            let notifications = self.peripheral.notifications().await?;
+            let frames = notifications.map(|notification| Frame {
+                characteristic: notification.uuid,
+                data: notification.value,
+            });
+
+            Ok(frames.boxed())
+        }
+        .boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        let peripheral = self.peripheral.clone();
+
+        let link_lost = async move {
+            loop {
+                tokio::time::sleep(LINK_CHECK_INTERVAL).await;
+
+                if !peripheral.is_connected().await.unwrap_or(false) {
+                    return ConnectionEvent::Disconnected;
+                }
+            }
+        };
+
+        stream::once(link_lost).boxed()
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        async move { Ok(self.peripheral.is_connected().await?) }.boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.peripheral.disconnect().await?;
+            // Only once done, the drop is left to disconnect otherwise
+            self.is_disconnected.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl Drop for BleTransport {
+    fn drop(&mut self) {
+        if self.is_disconnected.load(Ordering::SeqCst) {
+            return;
+        }
+
+        // Disconnecting is async, so it can only be done if there is a runtime to spawn it on
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                let peripheral = self.peripheral.clone();
+                runtime.spawn(async move {
+                    if let Err(err) = peripheral.disconnect().await {
+                        log::warn!("WalkingPad disconnection failed: {}", err);
+                    }
+                });
+            }
+            Err(_) => log::warn!("WalkingPad dropped outside of a runtime, staying connected"),
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! Shares a treadmill's connection with other machines over TCP, so that it can be driven from
+//! further away than Bluetooth reaches.
+//!
+//! The bridge only relays the raw frames of the treadmill's protocol. Each message is prefixed
+//! by its length as a big endian u16, and starts with its kind:
+//!
+//! - hello, from the client: the token, if the bridge asks for one
+//! - welcome, from the bridge: the treadmill's protocol
+//! - refused, from the bridge: the token is wrong, the connection is closed
+//! - write, from the client: a frame to write to the treadmill
+//! - notify, from the bridge: the characteristic's UUID and the frame notified on it
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use constant_time_eq::constant_time_eq;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::transport::{broadcast_stream, Frame, FrameStream, Transport};
+use crate::{Backend, ConnectionEvent, Error, Result, WalkingPad};
+
+const HELLO: u8 = 0x01;
+const WELCOME: u8 = 0x02;
+const REFUSED: u8 = 0x03;
+const WRITE: u8 = 0x04;
+const NOTIFY: u8 = 0x05;
+
+/// How long a client has to say hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default)]
+pub struct BridgeOptions {
+    /// Clients must present this token, anyone reaching the port can drive the treadmill
+    /// otherwise.
+    pub token: Option<String>,
+}
+
+/// Serves a connected treadmill to the clients of a TCP port.
+pub struct Bridge {
+    listener: TcpListener,
+    walkingpad: Arc<WalkingPad>,
+    options: BridgeOptions,
+}
+
+impl Bridge {
+    pub async fn bind(
+        address: impl ToSocketAddrs,
+        walkingpad: WalkingPad,
+        options: BridgeOptions,
+    ) -> Result<Bridge> {
+        Ok(Bridge {
+            listener: TcpListener::bind(address).await?,
+            walkingpad: Arc::new(walkingpad),
+            options,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves clients until the link to the treadmill is lost, which closes their connections.
+    pub async fn run(self) -> Result<()> {
+        let (closing, _) = broadcast::channel::<()>(1);
+        let mut link_lost = Box::pin(self.walkingpad.link_lost());
+
+        loop {
+            let accepted = match future::select(Box::pin(self.listener.accept()), link_lost).await {
+                Either::Left((accepted, pending)) => {
+                    link_lost = pending;
+                    accepted
+                }
+                Either::Right(_) => return Ok(()),
+            };
+
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    log::warn!("Bridge client not accepted: {}", err);
+                    continue;
+                }
+            };
+
+            let walkingpad = self.walkingpad.clone();
+            let token = self.options.token.clone();
+            let mut closing = closing.subscribe();
+
+            tokio::spawn(async move {
+                log::info!("Bridge client {} connected", peer);
+
+                let serve = serve_client(stream, &walkingpad, token.as_deref());
+                match future::select(Box::pin(serve), Box::pin(closing.recv())).await {
+                    Either::Left((Err(err), _)) => log::warn!("Bridge client {}: {}", peer, err),
+                    Either::Left((Ok(()), _)) | Either::Right(_) => {}
+                }
+
+                log::info!("Bridge client {} disconnected", peer);
+            });
+        }
+    }
+}
+
+async fn serve_client(
+    mut stream: TcpStream,
+    walkingpad: &WalkingPad,
+    token: Option<&str>,
+) -> Result<()> {
+    let hello = tokio::time::timeout(HELLO_TIMEOUT, read_message(&mut stream))
+        .await
+        .map_err(|_| Error::Timeout)??;
+
+    let presented = match hello.split_first() {
+        Some((&HELLO, presented)) => presented,
+        _ => return Err(Error::ConnectionError("Expected a hello".to_string())),
+    };
+    // Compared in constant time, so that the token can't be guessed a byte at a time
+    if token.is_some_and(|token| !constant_time_eq(token.as_bytes(), presented)) {
+        write_message(&mut stream, &[REFUSED]).await?;
+        return Err(Error::ConnectionError("Wrong token".to_string()));
+    }
+
+    // Subscribed before welcoming, so that no answer to the client's first write is missed
+    let mut frames = walkingpad.transport.frames().await?;
+    let backend = match walkingpad.backend {
+        Backend::WalkingPad => 0,
+        Backend::Ftms => 1,
+    };
+    write_message(&mut stream, &[WELCOME, backend]).await?;
+
+    let (mut reader, mut writer) = stream.into_split();
+
+    let relay_frames = async {
+        while let Some(frame) = frames.next().await {
+            let mut message = vec![NOTIFY];
+            message.extend_from_slice(frame.characteristic.as_bytes());
+            message.extend_from_slice(&frame.data);
+            write_message(&mut writer, &message).await?;
+        }
+        Ok(())
+    };
+
+    let relay_writes = async {
+        loop {
+            let message = match read_message(&mut reader).await {
+                Ok(message) => message,
+                // The client hung up
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            match message.split_first() {
+                Some((&WRITE, frame)) => walkingpad.transport.write(frame).await?,
+                _ => log::warn!("Unexpected bridge message {:02x?}", message),
+            }
+        }
+    };
+
+    let result = match future::select(Box::pin(relay_frames), Box::pin(relay_writes)).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    };
+    result
+}
+
+/// A transport to a treadmill shared by a [`Bridge`].
+pub struct TcpTransport {
+    writer: Mutex<OwnedWriteHalf>,
+    backend: Backend,
+    frames: broadcast::Sender<Frame>,
+    events: broadcast::Sender<ConnectionEvent>,
+    is_connected: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+}
+
+impl TcpTransport {
+    pub async fn connect(address: impl ToSocketAddrs, token: Option<&str>) -> Result<TcpTransport> {
+        let mut stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+
+        let mut hello = vec![HELLO];
+        hello.extend_from_slice(token.unwrap_or_default().as_bytes());
+        write_message(&mut stream, &hello).await?;
+
+        let backend = match read_message(&mut stream).await?[..] {
+            [WELCOME, 0] => Backend::WalkingPad,
+            [WELCOME, 1] => Backend::Ftms,
+            [REFUSED] => {
+                return Err(Error::ConnectionError(
+                    "The bridge refused the token".to_string(),
+                ))
+            }
+            ref other => {
+                return Err(Error::ConnectionError(format!(
+                    "Unexpected bridge message {:02x?}",
+                    other
+                )))
+            }
+        };
+
+        let (mut reader, writer) = stream.into_split();
+        let (frames, _) = broadcast::channel(64);
+        let (events, _) = broadcast::channel(16);
+        let is_connected = Arc::new(AtomicBool::new(true));
+
+        let reader = {
+            let frames = frames.clone();
+            let events = events.clone();
+            let is_connected = is_connected.clone();
+
+            tokio::spawn(async move {
+                while let Ok(message) = read_message(&mut reader).await {
+                    let Some((&NOTIFY, notification)) = message.split_first() else {
+                        log::warn!("Unexpected bridge message {:02x?}", message);
+                        continue;
+                    };
+                    if notification.len() < 16 {
+                        log::warn!("Bridge notification too short: {:02x?}", message);
+                        continue;
+                    }
+
+                    let (uuid, data) = notification.split_at(16);
+                    let _ = frames.send(Frame {
+                        characteristic: Uuid::from_slice(uuid).unwrap(),
+                        data: data.to_vec(),
+                    });
+                }
+
+                is_connected.store(false, Ordering::SeqCst);
+                let _ = events.send(ConnectionEvent::Disconnected);
+            })
+        };
+
+        Ok(TcpTransport {
+            writer: Mutex::new(writer),
+            backend,
+            frames,
+            events,
+            is_connected,
+            reader,
+        })
+    }
+
+    /// The protocol of the treadmill behind the bridge.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+}
+
+impl Transport for TcpTransport {
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            if !self.is_connected.load(Ordering::SeqCst) {
+                return Err(Error::ConnectionClosed);
+            }
+
+            let mut message = vec![WRITE];
+            message.extend_from_slice(frame);
+            write_message(&mut *self.writer.lock().await, &message)
+                .await
+                .map_err(|err| match err {
+                    Error::Io(err) => Error::WriteFailed(err.into()),
+                    err => err,
+                })
+        }
+        .boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        let frames = broadcast_stream(self.frames.subscribe());
+        future::ready(Ok(frames)).boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        broadcast_stream(self.events.subscribe())
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        future::ready(Ok(self.is_connected.load(Ordering::SeqCst))).boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.reader.abort();
+            self.is_connected.store(false, Ordering::SeqCst);
+            let _ = self.events.send(ConnectionEvent::Disconnected);
+            Ok(self.writer.lock().await.shutdown().await?)
+        }
+        .boxed()
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn write_message(writer: &mut (impl AsyncWrite + Unpin), message: &[u8]) -> Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| Error::ConnectionError("Bridge message too long".to_string()))?;
+
+    let mut buf = Vec::with_capacity(2 + message.len());
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(message);
+    writer.write_all(&buf).await?;
+
+    Ok(())
+}
+
+/// Returns [`Error::ConnectionClosed`] if the peer hung up between two messages.
+async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let mut len = [0; 2];
+    if let Err(err) = reader.read_exact(&mut len).await {
+        return Err(match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => Error::ConnectionClosed,
+            _ => err.into(),
+        });
+    }
+
+    let mut message = vec![0; u16::from_be_bytes(len).into()];
+    reader.read_exact(&mut message).await?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_transport;
+    use btleplug::api::bleuuid::uuid_from_u16;
+    use walkingpad_protocol::fixtures::STATE;
+    use walkingpad_protocol::{request, Response};
+
+    #[tokio::test]
+    async fn test() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let options = BridgeOptions {
+            token: Some("secret".to_string()),
+        };
+        let bridge = Bridge::bind("127.0.0.1:0", walkingpad, options)
+            .await
+            .unwrap();
+        let address = bridge.local_addr().unwrap();
+        let bridge = tokio::spawn(bridge.run());
+
+        let refused = TcpTransport::connect(address, Some("guess")).await;
+        assert!(matches!(refused, Err(Error::ConnectionError(_))));
+        assert!(!refused.err().unwrap().is_retryable());
+
+        let client = TcpTransport::connect(address, Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(client.backend(), Backend::WalkingPad);
+        let client = WalkingPad::with_transport(client, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let mut responses = client.responses().await.unwrap();
+
+        client.send(request::get::state()).await.unwrap();
+        assert_eq!(
+            peer.next_write().await.unwrap(),
+            request::get::state().as_bytes()
+        );
+
+        peer.notify(uuid_from_u16(0xfe01), &STATE);
+        assert_eq!(
+            responses.next().await,
+            Some(Response::parse(&STATE).unwrap())
+        );
+
+        peer.drop_link();
+        bridge.await.unwrap().unwrap();
+        client.link_lost().await;
+        assert!(client
+            .send(request::stop())
+            .await
+            .is_err_and(|err| err.is_retryable()));
+    }
+}
@@ -0,0 +1,379 @@
+//! Records the traffic of a connection, and plays it back, so that what happened on a user's
+//! treadmill can be reproduced offline.
+//!
+//! Captures are JSON lines files, each line being a [`CaptureRecord`].
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::transport::{broadcast_stream, Frame, FrameStream, Transport};
+use crate::{Backend, ConnectionEvent, Error, Result};
+
+/// A frame going through the connection.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    #[serde(flatten)]
+    pub item: Captured,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Captured {
+    /// Written by the client.
+    Write { data: Vec<u8> },
+    /// Notified by the treadmill.
+    Notify { characteristic: Uuid, data: Vec<u8> },
+}
+
+impl CaptureRecord {
+    fn now(item: Captured) -> CaptureRecord {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        CaptureRecord {
+            time: time.as_millis() as u64,
+            item,
+        }
+    }
+}
+
+/// Reads every record of a capture.
+pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<CaptureRecord>> {
+    let file = BufReader::new(File::open(path)?);
+
+    file.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?)
+                .map_err(|err| Error::InvalidCapture(format!("line {}: {}", i + 1, err)))
+        })
+        .collect()
+}
+
+/// Tees the traffic of another transport into a capture, appended to if it already exists.
+pub struct CaptureTransport<T> {
+    inner: T,
+    records: mpsc::UnboundedSender<CaptureRecord>,
+    tap: JoinHandle<()>,
+}
+
+impl<T: Transport> CaptureTransport<T> {
+    pub async fn new(inner: T, path: impl AsRef<Path>) -> Result<CaptureTransport<T>> {
+        let file = File::options().create(true).append(true).open(path)?;
+
+        // The file is written to away from the async tasks, which mustn't block on it
+        let (records, mut records_out) = mpsc::unbounded_channel::<CaptureRecord>();
+        tokio::task::spawn_blocking(move || {
+            let mut file = BufWriter::new(file);
+
+            while let Some(record) = records_out.blocking_recv() {
+                if let Err(err) = write_record(&mut file, &record) {
+                    log::warn!("Unable to capture a frame: {}", err);
+                }
+            }
+        });
+
+        // Every notification is recorded, whether the client listens to it or not
+        let mut frames = inner.frames().await?;
+        let tap = {
+            let records = records.clone();
+
+            tokio::spawn(async move {
+                while let Some(frame) = frames.next().await {
+                    let _ = records.send(CaptureRecord::now(Captured::Notify {
+                        characteristic: frame.characteristic,
+                        data: frame.data,
+                    }));
+                }
+            })
+        };
+
+        Ok(CaptureTransport {
+            inner,
+            records,
+            tap,
+        })
+    }
+}
+
+/// Flushed right away, so that the capture survives a crash.
+fn write_record(file: &mut BufWriter<File>, record: &CaptureRecord) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *file, record)?;
+    writeln!(file)?;
+    file.flush()
+}
+
+impl<T: Transport> Transport for CaptureTransport<T> {
+    /// Only the frames actually written are recorded.
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.inner.write(frame).await?;
+
+            let data = frame.to_vec();
+            let _ = self
+                .records
+                .send(CaptureRecord::now(Captured::Write { data }));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        self.inner.frames()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        self.inner.events()
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        self.inner.is_connected()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        self.inner.disconnect()
+    }
+}
+
+impl<T> Drop for CaptureTransport<T> {
+    fn drop(&mut self) {
+        self.tap.abort();
+    }
+}
+
+/// Plays the notifications of a capture back, at the pace they were recorded at or faster.
+///
+/// The frames written by the client are ignored: the capture plays out whatever it asks for.
+/// The link is lost once the capture is over.
+pub struct ReplayTransport {
+    backend: Backend,
+    speed: f64,
+    records: Mutex<Option<Vec<CaptureRecord>>>,
+    frames: broadcast::Sender<Frame>,
+    events: broadcast::Sender<ConnectionEvent>,
+    is_connected: Arc<AtomicBool>,
+    playback: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ReplayTransport {
+    /// Plays the capture `speed` times faster than it was recorded.
+    pub fn open(path: impl AsRef<Path>, speed: f64) -> Result<ReplayTransport> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Error::InvalidCapture(format!(
+                "{} isn't a valid replay speed",
+                speed
+            )));
+        }
+
+        let records = read_capture(path)?;
+
+        let is_ftms = |characteristic: &Uuid| {
+            Backend::Ftms
+                .notify_characteristic_uuids()
+                .contains(characteristic)
+        };
+        let backend = match records.iter().find_map(|record| match &record.item {
+            Captured::Notify { characteristic, .. } => Some(characteristic),
+            Captured::Write { .. } => None,
+        }) {
+            Some(characteristic) if is_ftms(characteristic) => Backend::Ftms,
+            _ => Backend::WalkingPad,
+        };
+
+        let (frames, _) = broadcast::channel(64);
+        let (events, _) = broadcast::channel(16);
+
+        Ok(ReplayTransport {
+            backend,
+            speed,
+            records: Mutex::new(Some(records)),
+            frames,
+            events,
+            is_connected: Arc::new(AtomicBool::new(true)),
+            playback: Mutex::new(None),
+        })
+    }
+
+    /// The protocol spoken in the capture.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Started by the first subscription to the frames, which would miss them otherwise.
+    fn play(&self) {
+        let Some(records) = self.records.lock().unwrap().take() else {
+            return;
+        };
+
+        let frames = self.frames.clone();
+        let events = self.events.clone();
+        let is_connected = self.is_connected.clone();
+        let speed = self.speed;
+
+        let playback = tokio::spawn(async move {
+            let start = Instant::now();
+            let first = records.first().map(|record| record.time).unwrap_or(0);
+
+            for record in records {
+                let Captured::Notify {
+                    characteristic,
+                    data,
+                } = record.item
+                else {
+                    continue;
+                };
+
+                let offset = Duration::from_millis(record.time.saturating_sub(first));
+                tokio::time::sleep_until(start + offset.div_f64(speed)).await;
+
+                let _ = frames.send(Frame {
+                    characteristic,
+                    data,
+                });
+            }
+
+            is_connected.store(false, Ordering::SeqCst);
+            let _ = events.send(ConnectionEvent::Disconnected);
+        });
+
+        *self.playback.lock().unwrap() = Some(playback);
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        if !self.is_connected.load(Ordering::SeqCst) {
+            return future::ready(Err(Error::ConnectionClosed)).boxed();
+        }
+
+        log::debug!("Replaying, {:02x?} isn't written", frame);
+        future::ready(Ok(())).boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        let frames = broadcast_stream(self.frames.subscribe());
+        self.play();
+        future::ready(Ok(frames)).boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        broadcast_stream(self.events.subscribe())
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        future::ready(Ok(self.is_connected.load(Ordering::SeqCst))).boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        if let Some(playback) = self.playback.lock().unwrap().take() {
+            playback.abort();
+        }
+        self.is_connected.store(false, Ordering::SeqCst);
+        let _ = self.events.send(ConnectionEvent::Disconnected);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+impl Drop for ReplayTransport {
+    fn drop(&mut self) {
+        if let Some(playback) = self.playback.lock().unwrap().take() {
+            playback.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{memory_transport, WalkingPad};
+    use btleplug::api::bleuuid::uuid_from_u16;
+    use walkingpad_protocol::fixtures::STATE;
+    use walkingpad_protocol::{request, Response};
+
+    #[tokio::test]
+    async fn test() {
+        let path = std::env::temp_dir().join(format!("capture-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (transport, mut peer) = memory_transport();
+        let transport = CaptureTransport::new(transport, &path).await.unwrap();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let mut responses = walkingpad.responses().await.unwrap();
+
+        walkingpad.send(request::get::state()).await.unwrap();
+        peer.next_write().await.unwrap();
+        peer.notify(uuid_from_u16(0xfe01), &STATE);
+        responses.next().await.unwrap();
+        // Lets the tap record the notification before it's dropped along with the client
+        tokio::task::yield_now().await;
+        drop(walkingpad);
+
+        // The records are written in the background
+        let mut records = read_capture(&path).unwrap();
+        for _ in 0..100 {
+            if records.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            records = read_capture(&path).unwrap();
+        }
+        let items: Vec<_> = records.into_iter().map(|record| record.item).collect();
+        assert_eq!(
+            items,
+            [
+                Captured::Write {
+                    data: request::get::state().as_bytes().to_vec()
+                },
+                Captured::Notify {
+                    characteristic: uuid_from_u16(0xfe01),
+                    data: STATE.to_vec()
+                },
+            ]
+        );
+
+        // A frame which couldn't be written isn't recorded
+        let (transport, _peer) = memory_transport();
+        let transport = CaptureTransport::new(transport, &path).await.unwrap();
+        transport.disconnect().await.unwrap();
+        assert!(transport.write(request::start().as_bytes()).await.is_err());
+        drop(transport);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(read_capture(&path).unwrap().len(), 2);
+
+        let transport = ReplayTransport::open(&path, 100.0).unwrap();
+        assert_eq!(transport.backend(), Backend::WalkingPad);
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let mut responses = walkingpad.responses().await.unwrap();
+
+        assert_eq!(
+            responses.next().await,
+            Some(Response::parse(&STATE).unwrap())
+        );
+        walkingpad.link_lost().await;
+
+        std::fs::remove_file(&path).unwrap();
+        let missing = ReplayTransport::open(&path, 1.0).err().unwrap();
+        assert!(matches!(missing, Error::Io(_)));
+        assert!(!missing.is_retryable());
+    }
+}
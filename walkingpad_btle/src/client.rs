@@ -0,0 +1,585 @@
+//! Async client, running on the caller's tokio runtime.
+
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::WriteType;
+use futures::future;
+use futures::stream::{Stream, StreamExt};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use uuid::Uuid;
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{Settings, State, StoredStats};
+use walkingpad_protocol::{Request, Response};
+
+use crate::ble::BleTransport;
+use crate::bridge::TcpTransport;
+use crate::capture::{CaptureTransport, ReplayTransport};
+use crate::discovery::{self, BDAddr, ConnectOptions, DiscoveredDevice, Target};
+use crate::scheduler::SchedulerOptions;
+use crate::transport::{Frame, Transport};
+use crate::verified::VerifyOptions;
+use crate::{ftms, ConnectionEvent, Error, Result};
+#[cfg(feature = "sim")]
+use crate::{SimTransport, Simulator};
+
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Response> + Send>>;
+
+pub type ReceivedStream = Pin<Box<dyn Stream<Item = Received> + Send>>;
+
+/// A response, or a frame which couldn't be decoded, stamped as soon as it's received so that
+/// slow consumers don't skew their timings.
+#[derive(Clone, Debug)]
+pub struct Received {
+    /// Counts the items delivered, starting from 0.
+    pub sequence: u64,
+    /// When the item was received, to measure durations.
+    pub instant: std::time::Instant,
+    /// When the item was received, to be shown or stored.
+    pub time: SystemTime,
+    pub item: std::result::Result<Response, MalformedFrame>,
+}
+
+/// A frame the treadmill's protocol couldn't make sense of.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MalformedFrame {
+    pub frame: Frame,
+    pub reason: String,
+}
+
+impl Received {
+    pub(crate) fn new(sequence: u64, item: std::result::Result<Response, MalformedFrame>) -> Self {
+        Received {
+            sequence,
+            instant: std::time::Instant::now(),
+            time: SystemTime::now(),
+            item,
+        }
+    }
+
+    /// Returns the response, logging malformed frames.
+    pub(crate) fn into_response(self) -> Option<Response> {
+        match self.item {
+            Ok(response) => Some(response),
+            Err(malformed) => {
+                log::error!("{}: `{:?}`", malformed.reason, malformed.frame);
+                None
+            }
+        }
+    }
+}
+
+/// An async connection to a treadmill, speaking either the WalkingPad protocol or FTMS.
+///
+/// Each handle owns its connection, which is closed by [`disconnect`](WalkingPad::disconnect)
+/// or when the handle is dropped. Several handles can be held at once, each connected to a
+/// different treadmill.
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use walkingpad_btle::WalkingPad;
+/// use walkingpad_protocol::request;
+///
+/// # async fn example() -> walkingpad_btle::Result<()> {
+/// let walkingpad = WalkingPad::connect().await?;
+/// let mut responses = walkingpad.responses().await?;
+///
+/// walkingpad.send(request::get::state()).await?;
+/// if let Some(response) = responses.next().await {
+///     println!("{}", response);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct WalkingPad {
+    pub(crate) transport: Box<dyn Transport>,
+    pub(crate) backend: Backend,
+    address: Option<BDAddr>,
+    last_write: Mutex<Option<Instant>>,
+    min_write_interval: Duration,
+    query_options: QueryOptions,
+    pub(crate) verify_options: VerifyOptions,
+}
+
+/// How long to wait for the answer to a query, and how many more times to ask for it.
+#[derive(Copy, Clone, Debug)]
+pub struct QueryOptions {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for QueryOptions {
+    fn default() -> QueryOptions {
+        QueryOptions {
+            timeout: Duration::from_secs(1),
+            retries: 2,
+        }
+    }
+}
+
+impl WalkingPad {
+    /// Connects to the first treadmill found which isn't already connected.
+    pub async fn connect() -> Result<WalkingPad> {
+        WalkingPad::connect_with(&ConnectOptions::default()).await
+    }
+
+    /// Connects to the treadmill targeted by the options, scanning for it if it's in range.
+    pub async fn connect_with(options: &ConnectOptions) -> Result<WalkingPad> {
+        match &options.target {
+            Target::Any | Target::Address(_) | Target::Name(_) => {
+                let mut devices = discovery::find(options).await?;
+
+                while let Some(device) = devices.next().await {
+                    match WalkingPad::connect_device_with(&device, options).await {
+                        // An FTMS machine which doesn't advertise what it is may be a bike
+                        Err(Error::CharacteristicMissing { uuid })
+                            if uuid == ftms::TREADMILL_DATA_UUID =>
+                        {
+                            log::info!("{} isn't a treadmill, skipping it", device.address);
+                        }
+                        result => return result,
+                    }
+                }
+
+                Err(Error::NoWalkingPadFound)
+            }
+            #[cfg(feature = "sim")]
+            Target::Simulator => {
+                let transport = SimTransport::new(Simulator::new());
+                WalkingPad::with_transport_options(transport, Backend::WalkingPad, options).await
+            }
+            Target::Bridge { address, token } => {
+                let transport = TcpTransport::connect(address.as_str(), token.as_deref()).await?;
+                let backend = transport.backend();
+                WalkingPad::with_transport_options(transport, backend, options).await
+            }
+            Target::Replay { path, speed } => {
+                let transport = ReplayTransport::open(path, *speed)?;
+                let backend = transport.backend();
+                WalkingPad::with_transport_options(transport, backend, options).await
+            }
+        }
+    }
+
+    /// Connects to a treadmill found by [`scan`](crate::scan).
+    pub async fn connect_device(device: &DiscoveredDevice) -> Result<WalkingPad> {
+        WalkingPad::connect_device_with(device, &ConnectOptions::default()).await
+    }
+
+    /// Connects to a treadmill found by [`scan`](crate::scan), ignoring the options' target.
+    pub async fn connect_device_with(
+        device: &DiscoveredDevice,
+        options: &ConnectOptions,
+    ) -> Result<WalkingPad> {
+        let backend = device.backend();
+        let write_type = options.scheduler.write_type.unwrap_or(backend.write_type());
+        let transport =
+            BleTransport::connect(device.peripheral.clone(), backend, write_type).await?;
+        let address = transport.address();
+
+        let mut walkingpad =
+            WalkingPad::with_transport_options(transport, backend, options).await?;
+        walkingpad.address = Some(address);
+
+        Ok(walkingpad)
+    }
+
+    /// Applies the options which don't depend on the transport, capturing its traffic if asked.
+    async fn with_transport_options(
+        transport: impl Transport + 'static,
+        backend: Backend,
+        options: &ConnectOptions,
+    ) -> Result<WalkingPad> {
+        let mut walkingpad = match &options.capture {
+            Some(path) => {
+                let transport = CaptureTransport::new(transport, path).await?;
+                WalkingPad::with_transport(transport, backend).await?
+            }
+            None => WalkingPad::with_transport(transport, backend).await?,
+        };
+
+        walkingpad.set_min_write_interval(options.scheduler.min_interval);
+        walkingpad.set_query_options(options.query);
+        walkingpad.set_verify_options(options.verify);
+
+        Ok(walkingpad)
+    }
+
+    /// Runs the client over any transport, e.g. a [`MemoryTransport`](crate::MemoryTransport)
+    /// in tests.
+    pub async fn with_transport(
+        transport: impl Transport + 'static,
+        backend: Backend,
+    ) -> Result<WalkingPad> {
+        if backend == Backend::Ftms {
+            transport.write(&ftms::REQUEST_CONTROL).await?;
+        }
+
+        Ok(WalkingPad {
+            transport: Box::new(transport),
+            backend,
+            address: None,
+            last_write: Mutex::new(None),
+            min_write_interval: SchedulerOptions::default().min_interval,
+            query_options: QueryOptions::default(),
+            verify_options: VerifyOptions::default(),
+        })
+    }
+
+    pub fn set_min_write_interval(&mut self, interval: Duration) {
+        self.min_write_interval = interval;
+    }
+
+    pub fn set_query_options(&mut self, options: QueryOptions) {
+        self.query_options = options;
+    }
+
+    /// Closes the connection, ending the response streams.
+    pub async fn disconnect(self) -> Result<()> {
+        self.transport.disconnect().await
+    }
+
+    pub async fn is_connected(&self) -> Result<bool> {
+        self.transport.is_connected().await
+    }
+
+    /// The Bluetooth address of the treadmill, unless connected through another transport.
+    pub fn address(&self) -> Option<BDAddr> {
+        self.address
+    }
+
+    /// Resolves once the link is lost, which doesn't always end the response streams.
+    pub async fn link_lost(&self) {
+        let mut events = self.transport.events();
+
+        // Checked once subscribed, so that a loss in between isn't missed
+        if !self.is_connected().await.unwrap_or(false) {
+            return;
+        }
+
+        while let Some(event) = events.next().await {
+            if event == ConnectionEvent::Disconnected {
+                return;
+            }
+        }
+    }
+
+    /// Writes a request, waiting first if the previous write is too recent.
+    /// Requests the treadmill can't carry out are silently dropped.
+    pub async fn send(&self, request: Request) -> Result<()> {
+        let Some(frame) = self.backend.encode(&request) else {
+            log::debug!(
+                "{:?} isn't supported by {:?} treadmills",
+                request,
+                self.backend
+            );
+            return Ok(());
+        };
+
+        let mut last_write = self.last_write.lock().await;
+
+        if let Some(last_write) = *last_write {
+            tokio::time::sleep_until(last_write + self.min_write_interval).await;
+        }
+
+        self.transport.write(&frame).await?;
+
+        *last_write = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// Resolves once a request would be written right away.
+    pub(crate) async fn write_slot(&self) {
+        let last_write = *self.last_write.lock().await;
+
+        if let Some(last_write) = last_write {
+            tokio::time::sleep_until(last_write + self.min_write_interval).await;
+        }
+    }
+
+    /// Polls the treadmill for its state.
+    ///
+    /// FTMS treadmills can't be polled, the next state they notify is returned instead.
+    pub async fn get_state(&self) -> Result<State> {
+        self.query(request::get::state(), |response| match response {
+            Response::State(state) => Some(state),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn get_settings(&self) -> Result<Settings> {
+        self.ensure_native()?;
+        self.query(request::get::settings(), |response| match response {
+            Response::Settings(settings) => Some(settings),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn get_stored_stats(&self, id: u8) -> Result<StoredStats> {
+        self.ensure_native()?;
+        self.query(request::get::stored_stats(id), stored_stats)
+            .await
+    }
+
+    pub async fn get_latest_stored_stats(&self) -> Result<StoredStats> {
+        self.ensure_native()?;
+        self.query(request::get::latest_stored_stats(), stored_stats)
+            .await
+    }
+
+    fn ensure_native(&self) -> Result<()> {
+        match self.backend {
+            Backend::WalkingPad => Ok(()),
+            Backend::Ftms => Err(Error::Unsupported),
+        }
+    }
+
+    /// Sends the request until the treadmill answers it, as told by `answer`.
+    /// The other responses received meanwhile still reach the [`responses`] streams.
+    ///
+    /// [`responses`]: WalkingPad::responses
+    async fn query<T>(
+        &self,
+        request: Request,
+        answer: impl Fn(Response) -> Option<T>,
+    ) -> Result<T> {
+        // Subscribed beforehand so that the answer can't be missed, and kept across the retries
+        // so that a late answer still counts
+        let mut responses = self.responses().await?;
+
+        for _ in 0..=self.query_options.retries {
+            self.send(request.clone()).await?;
+
+            let answered = async {
+                while let Some(response) = responses.next().await {
+                    if let Some(answer) = answer(response) {
+                        return Some(answer);
+                    }
+                }
+                None
+            };
+
+            match tokio::time::timeout(self.query_options.timeout, answered).await {
+                Ok(Some(answer)) => return Ok(answer),
+                Ok(None) => return Err(Error::ConnectionClosed),
+                Err(_) => log::warn!("{:?} timed out", request),
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    /// Returns a new stream of every response received from now on.
+    /// Malformed notifications are logged and skipped.
+    pub async fn responses(&self) -> Result<ResponseStream> {
+        let responses = self
+            .received()
+            .await?
+            .filter_map(|received| future::ready(received.into_response()));
+
+        Ok(Box::pin(responses))
+    }
+
+    /// Like [`responses`](WalkingPad::responses), stamping each item and delivering malformed
+    /// notifications along with their raw bytes.
+    pub async fn received(&self) -> Result<ReceivedStream> {
+        let frames = self.transport.frames().await?;
+        let backend = self.backend;
+        let mut ftms_decoder = ftms::Decoder::new();
+        let mut sequence = 0;
+
+        let received = frames.filter_map(move |frame| {
+            let item = backend.decode(&mut ftms_decoder, &frame).map(|decoded| {
+                let item = decoded.map_err(|reason| MalformedFrame { frame, reason });
+                sequence += 1;
+                Received::new(sequence - 1, item)
+            });
+            future::ready(item)
+        });
+
+        Ok(Box::pin(received))
+    }
+}
+
+fn stored_stats(response: Response) -> Option<StoredStats> {
+    match response {
+        Response::StoredStats(stats) => Some(stats),
+        _ => None,
+    }
+}
+
+/// Defines the protocols a treadmill can be driven with.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /// The WalkingPad's own protocol, over the 0xfe00 service.
+    WalkingPad,
+
+    /// The standard Fitness Machine Service, used by some newer KingSmith treadmills.
+    Ftms,
+}
+
+impl Backend {
+    pub(crate) fn write_characteristic_uuid(self) -> Uuid {
+        match self {
+            Backend::WalkingPad => uuid_from_u16(0xfe02),
+            Backend::Ftms => ftms::CONTROL_POINT_UUID,
+        }
+    }
+
+    pub(crate) fn notify_characteristic_uuids(self) -> &'static [Uuid] {
+        const WALKINGPAD: [Uuid; 1] = [uuid_from_u16(0xfe01)];
+        // Subscribing to the Control Point delivers the results of the operations written to it
+        const FTMS: [Uuid; 2] = [ftms::TREADMILL_DATA_UUID, ftms::CONTROL_POINT_UUID];
+
+        match self {
+            Backend::WalkingPad => &WALKINGPAD,
+            Backend::Ftms => &FTMS,
+        }
+    }
+
+    pub(crate) fn write_type(self) -> WriteType {
+        match self {
+            Backend::WalkingPad => WriteType::WithoutResponse,
+            Backend::Ftms => ftms::WRITE_TYPE,
+        }
+    }
+
+    fn encode(self, request: &Request) -> Option<Vec<u8>> {
+        match self {
+            Backend::WalkingPad => Some(request.as_bytes().to_vec()),
+            Backend::Ftms => ftms::encode(request),
+        }
+    }
+
+    /// Returns `None` for the frames which don't carry a response.
+    fn decode(
+        self,
+        ftms_decoder: &mut ftms::Decoder,
+        frame: &Frame,
+    ) -> Option<std::result::Result<Response, String>> {
+        match self {
+            Backend::WalkingPad => Some(
+                Response::parse(frame.data.as_slice())
+                    .map_err(|err| format!("malformed response: {}", err)),
+            ),
+            Backend::Ftms => ftms_decoder.decode(frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_transport;
+    use walkingpad_protocol::fixtures::{SETTINGS, STATE};
+
+    #[tokio::test(start_paused = true)]
+    async fn test() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let mut responses = walkingpad.responses().await.unwrap();
+        let mut received = walkingpad.received().await.unwrap();
+
+        let start = Instant::now();
+        walkingpad.send(request::get::state()).await.unwrap();
+        walkingpad.send(request::stop()).await.unwrap();
+        assert!(start.elapsed() >= SchedulerOptions::default().min_interval);
+
+        assert_eq!(
+            peer.next_write().await.unwrap(),
+            request::get::state().as_bytes()
+        );
+        assert_eq!(peer.next_write().await.unwrap(), request::stop().as_bytes());
+
+        peer.notify(uuid_from_u16(0xfe01), &STATE);
+        peer.notify(uuid_from_u16(0xfe01), &[0xf8, 0xa2]);
+        peer.notify(uuid_from_u16(0xfe01), &STATE);
+        assert_eq!(
+            responses.next().await,
+            Some(Response::parse(&STATE).unwrap())
+        );
+        // The malformed frame is skipped
+        assert_eq!(
+            responses.next().await,
+            Some(Response::parse(&STATE).unwrap())
+        );
+
+        let first = received.next().await.unwrap();
+        assert_eq!(first.sequence, 0);
+        assert_eq!(first.item, Ok(Response::parse(&STATE).unwrap()));
+        let malformed = received.next().await.unwrap();
+        assert_eq!(malformed.sequence, 1);
+        assert!(matches!(malformed.item, Err(m) if m.frame.data == [0xf8, 0xa2]));
+        assert!(malformed.instant >= first.instant);
+        assert_eq!(received.next().await.unwrap().sequence, 2);
+
+        peer.drop_link();
+        walkingpad.link_lost().await;
+        assert!(!walkingpad.is_connected().await.unwrap());
+        assert!(walkingpad.send(request::stop()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_query() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let mut responses = walkingpad.responses().await.unwrap();
+
+        let treadmill = async {
+            assert_eq!(
+                peer.next_write().await.unwrap(),
+                request::get::settings().as_bytes()
+            );
+            // An unsolicited state comes first, then the answer to the retry
+            peer.notify(uuid_from_u16(0xfe01), &STATE);
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            assert_eq!(
+                peer.next_write().await.unwrap(),
+                request::get::settings().as_bytes()
+            );
+            peer.notify(uuid_from_u16(0xfe01), &SETTINGS);
+        };
+
+        let (settings, _) = tokio::join!(walkingpad.get_settings(), treadmill);
+        assert_eq!(
+            Response::Settings(settings.unwrap()),
+            Response::parse(&SETTINGS).unwrap()
+        );
+        assert_eq!(
+            responses.next().await,
+            Some(Response::parse(&STATE).unwrap())
+        );
+
+        let start = Instant::now();
+        assert!(matches!(walkingpad.get_state().await, Err(Error::Timeout)));
+        assert!(start.elapsed() >= 3 * QueryOptions::default().timeout);
+        assert_eq!(std::iter::from_fn(|| peer.try_next_write()).count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_ftms() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::Ftms)
+            .await
+            .unwrap();
+
+        assert_eq!(peer.next_write().await.unwrap(), ftms::REQUEST_CONTROL);
+
+        // Settings can't be queried over FTMS
+        walkingpad.send(request::get::settings()).await.unwrap();
+        assert_eq!(peer.try_next_write(), None);
+        assert!(matches!(
+            walkingpad.get_settings().await,
+            Err(Error::Unsupported)
+        ));
+    }
+}
@@ -0,0 +1,455 @@
+//! Scanning for the treadmills in range.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::{Central, CentralEvent, ScanFilter};
+use btleplug::api::{Manager as _, Peripheral as _};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::{self, Stream, StreamExt};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use walkingpad_protocol::ftms::MachineTypes;
+
+use crate::client::Backend;
+use crate::{
+    ftms, Error, PollingOptions, QueryOptions, ReconnectPolicy, Result, SchedulerOptions,
+    VerifyOptions,
+};
+
+pub use btleplug::api::BDAddr;
+
+const WALKINGPAD_SERVICE_UUID: Uuid = uuid_from_u16(0xfe00);
+
+pub type DeviceStream = Pin<Box<dyn Stream<Item = DiscoveredDevice> + Send>>;
+
+/// The treadmill model, as guessed from the advertised name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Model {
+    A1,
+    A1Pro,
+    C1,
+    C2,
+    R1,
+    Z1,
+    /// A treadmill advertising the Fitness Machine Service under an unknown name.
+    Ftms,
+    Unknown,
+}
+
+impl Model {
+    fn guess(local_name: Option<&str>, services: &[Uuid]) -> Model {
+        match local_name {
+            Some("WalkingPad") => Model::A1,
+            Some(name) if name.contains("A1P") => Model::A1Pro,
+            Some(name) if name.contains("C1") => Model::C1,
+            Some(name) if name.contains("C2") => Model::C2,
+            Some(name) if name.contains("R1") || name.contains("BLR") => Model::R1,
+            Some(name) if name.contains("Z1") => Model::Z1,
+            _ if services.contains(&ftms::SERVICE_UUID) => Model::Ftms,
+            _ => Model::Unknown,
+        }
+    }
+}
+
+/// A treadmill seen while scanning.
+#[derive(Clone, Debug)]
+pub struct DiscoveredDevice {
+    pub address: BDAddr,
+    pub local_name: Option<String>,
+    /// In dBm, as of the device's discovery.
+    pub rssi: Option<i16>,
+    pub services: Vec<Uuid>,
+    /// What an FTMS device advertises being, if it does.
+    pub machine_types: Option<MachineTypes>,
+    pub model: Model,
+    /// The adapter the device was seen from.
+    pub adapter: String,
+    pub(crate) peripheral: Peripheral,
+}
+
+impl DiscoveredDevice {
+    /// The WalkingPad protocol is preferred when the treadmill speaks both.
+    pub(crate) fn backend(&self) -> Backend {
+        if self.local_name.as_deref() == Some("WalkingPad")
+            || self.services.contains(&WALKINGPAD_SERVICE_UUID)
+        {
+            Backend::WalkingPad
+        } else {
+            Backend::Ftms
+        }
+    }
+
+    /// FTMS devices which don't advertise what they are get told apart while connecting, by
+    /// whether they have the Treadmill Data characteristic.
+    fn is_treadmill(&self) -> bool {
+        self.local_name.as_deref() == Some("WalkingPad")
+            || self.services.contains(&WALKINGPAD_SERVICE_UUID)
+            || (self.services.contains(&ftms::SERVICE_UUID)
+                && self
+                    .machine_types
+                    .is_none_or(|types| types.contains(MachineTypes::TREADMILL)))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// How long to scan for.
+    pub duration: Duration,
+    /// Only scan from the adapters whose description contains this string, e.g. `"hci1"`.
+    /// Every adapter is used when unset.
+    pub adapter: Option<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            duration: Duration::from_secs(5),
+            adapter: None,
+        }
+    }
+}
+
+/// Which treadmill to connect to.
+///
+/// Targets parse from URIs, so that tools can be pointed at any treadmill with a single flag:
+///
+/// - `ble://`, `ble://<address>` or `ble://<name>`: a treadmill in range, see the variants below
+/// - `sim://`: a `Simulator`, with the `sim` feature
+/// - `tcp://[<token>@]<host>:<port>`: a treadmill shared by a [`Bridge`](crate::Bridge)
+/// - `replay://<path>[?speed=<factor>]`: a capture recorded with [`ConnectOptions::capture`]
+///
+/// ```rust
+/// use walkingpad_btle::Target;
+///
+/// let target: Target = "tcp://secret@192.168.1.20:4242".parse().unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Target {
+    /// The first treadmill found which isn't already connected.
+    #[default]
+    Any,
+    Address(BDAddr),
+    /// A local name, where `*` matches any sequence of characters, e.g. `"KS-*"`.
+    Name(String),
+    /// A fresh simulator, reset on each reconnection.
+    #[cfg(feature = "sim")]
+    Simulator,
+    Bridge {
+        /// The bridge's `host:port`.
+        address: String,
+        token: Option<String>,
+    },
+    Replay {
+        path: PathBuf,
+        /// How many times faster than recorded the capture plays.
+        speed: f64,
+    },
+}
+
+impl Target {
+    pub(crate) fn matches(&self, device: &DiscoveredDevice) -> bool {
+        match self {
+            Target::Any => true,
+            Target::Address(address) => device.address == *address,
+            Target::Name(pattern) => device
+                .local_name
+                .as_deref()
+                .is_some_and(|name| matches_pattern(pattern, name)),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Target> {
+        let invalid = |reason: &str| Error::InvalidUri(format!("{}: {}", uri, reason));
+
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| invalid("missing the scheme"))?;
+
+        match scheme {
+            "ble" if rest.is_empty() => Ok(Target::Any),
+            "ble" => Ok(match rest.parse() {
+                Ok(address) => Target::Address(address),
+                Err(_) => Target::Name(rest.to_string()),
+            }),
+            #[cfg(feature = "sim")]
+            "sim" if rest.is_empty() => Ok(Target::Simulator),
+            #[cfg(feature = "sim")]
+            "sim" => Err(invalid("the simulator takes no address")),
+            "tcp" => {
+                let (token, address) = match rest.rsplit_once('@') {
+                    Some((token, address)) => (Some(token.to_string()), address),
+                    None => (None, rest),
+                };
+                if !address.contains(':') {
+                    return Err(invalid("the bridge's port is missing"));
+                }
+
+                Ok(Target::Bridge {
+                    address: address.to_string(),
+                    token,
+                })
+            }
+            "replay" => {
+                let (path, speed) = match rest.split_once("?speed=") {
+                    Some((path, speed)) => match speed.parse::<f64>() {
+                        Ok(speed) if speed > 0.0 => (path, speed),
+                        _ => return Err(invalid("the speed must be a positive number")),
+                    },
+                    None => (rest, 1.0),
+                };
+                if path.is_empty() {
+                    return Err(invalid("the capture's path is missing"));
+                }
+
+                Ok(Target::Replay {
+                    path: PathBuf::from(path),
+                    speed,
+                })
+            }
+            _ => Err(invalid("unknown scheme")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub target: Target,
+    /// Connecting stops scanning as soon as the target is found, so the scan's duration is a
+    /// timeout.
+    pub scan: ScanOptions,
+    /// Only the connections of the blocking API reconnect, an async [`WalkingPad`] stands for a
+    /// single connection.
+    ///
+    /// [`WalkingPad`]: crate::WalkingPad
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Only the connections of the blocking API poll on their own.
+    pub polling: Option<PollingOptions>,
+    pub query: QueryOptions,
+    pub scheduler: SchedulerOptions,
+    pub verify: VerifyOptions,
+    /// Appends the traffic of the connection to this capture, which
+    /// [`ReplayTransport`](crate::ReplayTransport) plays back.
+    pub capture: Option<PathBuf>,
+}
+
+/// Scans for treadmills, yielding each one once as it's discovered.
+/// The stream ends after the scan's duration.
+pub async fn scan(options: &ScanOptions) -> Result<DeviceStream> {
+    let manager = Manager::new().await.map_err(Error::AdapterUnavailable)?;
+    let adapters = select_adapters(&manager, options.adapter.as_deref()).await?;
+
+    let (devices_in, devices_out) = mpsc::channel(16);
+
+    for adapter in adapters {
+        let info = adapter
+            .adapter_info()
+            .await
+            .map_err(Error::AdapterUnavailable)?;
+        let events = adapter.events().await.map_err(Error::AdapterUnavailable)?;
+        let filter = ScanFilter {
+            services: vec![WALKINGPAD_SERVICE_UUID, ftms::SERVICE_UUID],
+        };
+        adapter
+            .start_scan(filter)
+            .await
+            .map_err(Error::AdapterUnavailable)?;
+
+        let devices_in = devices_in.clone();
+        let deadline = Box::pin(tokio::time::sleep(options.duration));
+
+        tokio::spawn(async move {
+            let mut seen = HashSet::new();
+            let mut events = events.take_until(deadline);
+
+            // The adapter also remembers the peripherals of earlier scans, which may be long out
+            // of range, so only those advertising during this one are reported. Those already
+            // known are updated rather than discovered.
+            while let Some(event) = events.next().await {
+                let (CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id)) = event
+                else {
+                    continue;
+                };
+                let Ok(peripheral) = adapter.peripheral(&id).await else {
+                    continue;
+                };
+
+                let Some(device) = describe(peripheral, &info).await else {
+                    continue;
+                };
+
+                if !device.is_treadmill() || !seen.insert(device.address) {
+                    continue;
+                }
+
+                if devices_in.send(device).await.is_err() {
+                    break;
+                }
+            }
+
+            if let Err(err) = adapter.stop_scan().await {
+                log::warn!("Stopping the scan on {} failed: {}", info, err);
+            }
+        });
+    }
+
+    let devices = stream::unfold(devices_out, |mut devices| async move {
+        let device = devices.recv().await?;
+        Some((device, devices))
+    });
+
+    Ok(Box::pin(devices))
+}
+
+/// Scans for the treadmills matching the options' target, which aren't connected yet.
+pub(crate) async fn find(options: &ConnectOptions) -> Result<DeviceStream> {
+    let devices = scan(&options.scan).await?;
+    let target = options.target.clone();
+
+    let devices = devices.filter_map(move |device| {
+        let is_match = target.matches(&device);
+
+        // Already connected treadmills belong to another handle
+        async move {
+            let is_free = is_match && !device.peripheral.is_connected().await.unwrap_or(false);
+            is_free.then_some(device)
+        }
+    });
+
+    Ok(Box::pin(devices))
+}
+
+async fn select_adapters(manager: &Manager, name: Option<&str>) -> Result<Vec<Adapter>> {
+    let mut adapters = manager
+        .adapters()
+        .await
+        .map_err(Error::AdapterUnavailable)?;
+
+    if let Some(name) = name {
+        let mut selected = vec![];
+        for adapter in adapters {
+            let info = adapter
+                .adapter_info()
+                .await
+                .map_err(Error::AdapterUnavailable)?;
+            if info.contains(name) {
+                selected.push(adapter);
+            }
+        }
+        adapters = selected;
+    }
+
+    if adapters.is_empty() {
+        return Err(Error::NoAdapters);
+    }
+
+    Ok(adapters)
+}
+
+async fn describe(peripheral: Peripheral, adapter: &str) -> Option<DiscoveredDevice> {
+    let properties = peripheral.properties().await.ok()??;
+    let model = Model::guess(properties.local_name.as_deref(), &properties.services);
+    let machine_types = properties
+        .service_data
+        .get(&ftms::SERVICE_UUID)
+        .and_then(|data| MachineTypes::from_service_data(data));
+
+    Some(DiscoveredDevice {
+        address: properties.address,
+        local_name: properties.local_name,
+        rssi: properties.rssi,
+        services: properties.services,
+        machine_types,
+        model,
+        adapter: adapter.to_string(),
+        peripheral,
+    })
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+
+            // Let the wildcard swallow as many characters as needed for the rest to match
+            name.char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(name.len()))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert!(matches_pattern("WalkingPad", "WalkingPad"));
+        assert!(!matches_pattern("WalkingPad", "WalkingPad2"));
+        assert!(matches_pattern("KS-*", "KS-ST-A1P"));
+        assert!(matches_pattern("*A1P", "KS-ST-A1P"));
+        assert!(matches_pattern("KS-*-A1P", "KS-ST-A1P"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("KS-*-R1", "KS-ST-A1P"));
+
+        assert_eq!(Model::guess(Some("WalkingPad"), &[]), Model::A1);
+        assert_eq!(Model::guess(Some("KS-ST-A1P"), &[]), Model::A1Pro);
+        assert_eq!(
+            Model::guess(Some("Treadmill"), &[ftms::SERVICE_UUID]),
+            Model::Ftms
+        );
+        assert_eq!(Model::guess(None, &[]), Model::Unknown);
+
+        assert_eq!("ble://".parse::<Target>().unwrap(), Target::Any);
+        assert_eq!(
+            "ble://AA:BB:CC:DD:EE:FF".parse::<Target>().unwrap(),
+            Target::Address(BDAddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]))
+        );
+        assert_eq!(
+            "ble://KS-*".parse::<Target>().unwrap(),
+            Target::Name("KS-*".to_string())
+        );
+        #[cfg(feature = "sim")]
+        assert_eq!("sim://".parse::<Target>().unwrap(), Target::Simulator);
+        assert_eq!(
+            "tcp://secret@localhost:4242".parse::<Target>().unwrap(),
+            Target::Bridge {
+                address: "localhost:4242".to_string(),
+                token: Some("secret".to_string())
+            }
+        );
+        assert_eq!(
+            "replay:///tmp/run.jsonl".parse::<Target>().unwrap(),
+            Target::Replay {
+                path: PathBuf::from("/tmp/run.jsonl"),
+                speed: 1.0
+            }
+        );
+        assert_eq!(
+            "replay://run.jsonl?speed=10".parse::<Target>().unwrap(),
+            Target::Replay {
+                path: PathBuf::from("run.jsonl"),
+                speed: 10.0
+            }
+        );
+        assert!("replay://run.jsonl?speed=0".parse::<Target>().is_err());
+        assert!("tcp://localhost".parse::<Target>().is_err());
+        assert!("replay://".parse::<Target>().is_err());
+        assert!("usb://pad".parse::<Target>().is_err());
+        assert!("KS-ST-A1P".parse::<Target>().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Events derived from the differences between consecutive responses.
+
+use std::pin::Pin;
+
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
+use walkingpad_protocol::response::{MotorState, Settings, State};
+use walkingpad_protocol::{Mode, Response, Speed};
+
+use crate::{ConnectionEvent, Result, WalkingPad};
+
+pub type EventStream = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+/// [`Event::StepsMilestone`] is sent every time the steps of a run reach a multiple of this.
+pub const STEPS_MILESTONE: u32 = 1000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    MotorStarted,
+    MotorStopped,
+    SpeedChanged {
+        from: Speed,
+        to: Speed,
+    },
+    ModeChanged {
+        from: Mode,
+        to: Mode,
+    },
+    /// The steps of the current run reached this multiple of [`STEPS_MILESTONE`].
+    StepsMilestone(u32),
+    SettingsChanged(Settings),
+    /// Ends the stream.
+    Disconnected,
+}
+
+/// Compares each response with the previous one of its kind.
+/// The first state and settings received are only taken as a reference.
+#[derive(Default)]
+pub(crate) struct EventDetector {
+    state: Option<State>,
+    settings: Option<Settings>,
+}
+
+impl EventDetector {
+    pub(crate) fn update(&mut self, response: Response) -> Vec<Event> {
+        let mut events = vec![];
+
+        match response {
+            Response::State(state) => {
+                if let Some(previous) = self.state.as_ref() {
+                    state_events(previous, &state, &mut events);
+                }
+                self.state = Some(state);
+            }
+            Response::Settings(settings) => {
+                if self.settings.as_ref().is_some_and(|s| *s != settings) {
+                    events.push(Event::SettingsChanged(settings.clone()));
+                }
+                self.settings = Some(settings);
+            }
+            Response::StoredStats(_) => {}
+        }
+
+        events
+    }
+}
+
+fn state_events(previous: &State, state: &State, events: &mut Vec<Event>) {
+    let was_running = previous.motor_state == MotorState::Running;
+    let is_running = state.motor_state == MotorState::Running;
+
+    if !was_running && is_running {
+        events.push(Event::MotorStarted);
+    }
+    if previous.motor_state != MotorState::Stopped && state.motor_state == MotorState::Stopped {
+        events.push(Event::MotorStopped);
+    }
+
+    if previous.speed != state.speed {
+        events.push(Event::SpeedChanged {
+            from: previous.speed,
+            to: state.speed,
+        });
+    }
+
+    if previous.mode != state.mode {
+        events.push(Event::ModeChanged {
+            from: previous.mode,
+            to: state.mode,
+        });
+    }
+
+    // The steps start over with each run
+    let milestone = state.nb_steps / STEPS_MILESTONE;
+    if milestone > previous.nb_steps / STEPS_MILESTONE {
+        events.push(Event::StepsMilestone(milestone * STEPS_MILESTONE));
+    }
+}
+
+impl WalkingPad {
+    /// Returns a new stream of the events happening from now on, computed from the responses
+    /// received, whether polled by this client or someone else.
+    pub async fn events(&self) -> Result<EventStream> {
+        let mut detector = EventDetector::default();
+        let changes = self
+            .responses()
+            .await?
+            .flat_map(move |response| stream::iter(detector.update(response)));
+
+        let disconnection = self
+            .transport
+            .events()
+            .filter(|event| future::ready(*event == ConnectionEvent::Disconnected))
+            .take(1)
+            .map(|_| Event::Disconnected);
+
+        let events = stream::select(changes, disconnection).scan(false, |is_over, event| {
+            if *is_over {
+                return future::ready(None);
+            }
+            *is_over = event == Event::Disconnected;
+            future::ready(Some(event))
+        });
+
+        Ok(Box::pin(events))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn state(motor_state: MotorState, hm_per_hour: u8, nb_steps: u32) -> Response {
+        Response::State(State {
+            motor_state,
+            speed: Speed::from_hm_per_hour(hm_per_hour),
+            mode: Mode::Manual,
+            run_time: Duration::ZERO,
+            distance: 0,
+            nb_steps,
+            unknown: [0; 4],
+        })
+    }
+
+    #[test]
+    fn test() {
+        let mut detector = EventDetector::default();
+
+        assert_eq!(detector.update(state(MotorState::Stopped, 0, 0)), []);
+        assert_eq!(detector.update(state(MotorState::Starting, 0, 0)), []);
+        assert_eq!(
+            detector.update(state(MotorState::Running, 20, 10)),
+            [
+                Event::MotorStarted,
+                Event::SpeedChanged {
+                    from: Speed::from_hm_per_hour(0),
+                    to: Speed::from_hm_per_hour(20)
+                }
+            ]
+        );
+        assert_eq!(detector.update(state(MotorState::Running, 20, 999)), []);
+        assert_eq!(
+            detector.update(state(MotorState::Running, 20, 2001)),
+            [Event::StepsMilestone(2000)]
+        );
+        assert_eq!(
+            detector.update(state(MotorState::Stopped, 20, 0)),
+            [Event::MotorStopped]
+        );
+
+        let Response::State(mut auto) = state(MotorState::Stopped, 20, 0) else {
+            unreachable!()
+        };
+        auto.mode = Mode::Auto;
+        assert_eq!(
+            detector.update(Response::State(auto)),
+            [Event::ModeChanged {
+                from: Mode::Manual,
+                to: Mode::Auto
+            }]
+        );
+    }
+}
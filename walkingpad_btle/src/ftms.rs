@@ -0,0 +1,78 @@
+//! Support for treadmills exposing the standard Fitness Machine Service (FTMS) instead of the
+//! WalkingPad's own 0xfe00 service.
+
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use btleplug::api::WriteType;
+use uuid::Uuid;
+use walkingpad_protocol::ftms::{self, ControlPoint, ResultCode};
+use walkingpad_protocol::response::{MotorState, State};
+use walkingpad_protocol::{Mode, Request, Response, Speed};
+
+use crate::transport::Frame;
+
+pub(crate) const SERVICE_UUID: Uuid = uuid_from_u16(ftms::SERVICE_UUID);
+pub(crate) const TREADMILL_DATA_UUID: Uuid = uuid_from_u16(ftms::TREADMILL_DATA_UUID);
+pub(crate) const CONTROL_POINT_UUID: Uuid = uuid_from_u16(ftms::CONTROL_POINT_UUID);
+
+/// The Control Point rejects every operation until control has been requested.
+pub(crate) const REQUEST_CONTROL: [u8; 1] = [0x00];
+
+/// Operations written to the Control Point must be acknowledged by the treadmill.
+pub(crate) const WRITE_TYPE: WriteType = WriteType::WithResponse;
+
+/// Translates a request into the Control Point operation carrying it out.
+///
+/// FTMS treadmills notify their state on their own and have no settings or stored stats, so
+/// every other request is dropped.
+pub(crate) fn encode(request: &Request) -> Option<Vec<u8>> {
+    let op = ControlPoint::from_request(request)?;
+    let mut buf = [0; 3];
+    Some(op.encode(&mut buf).to_vec())
+}
+
+/// Accumulates Treadmill Data notifications into a [`State`].
+pub(crate) struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Decoder {
+        Decoder {
+            state: State {
+                motor_state: MotorState::Stopped,
+                speed: Speed::from_hm_per_hour(0),
+                // FTMS treadmills are driven by their clients, which is closest to Manual
+                mode: Mode::Manual,
+                run_time: Duration::ZERO,
+                distance: 0,
+                nb_steps: 0,
+                unknown: [0; 4],
+            },
+        }
+    }
+
+    /// Returns `None` for the frames which don't carry a response.
+    pub(crate) fn decode(&mut self, frame: &Frame) -> Option<Result<Response, String>> {
+        match frame.characteristic {
+            TREADMILL_DATA_UUID => match ftms::update_state(&mut self.state, &frame.data) {
+                Ok(()) => Some(Ok(self.state.clone().into())),
+                Err(err) => Some(Err(format!("malformed treadmill data: {}", err))),
+            },
+            CONTROL_POINT_UUID => {
+                if let [0x80, op_code, result, ..] = frame.data[..] {
+                    if result != ResultCode::Success as u8 {
+                        log::warn!(
+                            "FTMS operation {:#04x} failed with {:#04x}",
+                            op_code,
+                            result
+                        );
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Fetches the runs stored on the treadmill without wiping them, so that they are only cleared
+//! once the application has safely stored them.
+
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::StoredStats;
+use walkingpad_protocol::Response;
+
+use crate::{Error, Result, WalkingPadSender};
+
+/// Identifies a stored run across syncs, the treadmill's own ids being reused once cleared.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RunId {
+    pub start_time: u32,
+    pub duration: Duration,
+    pub distance: u32,
+    pub nb_steps: u32,
+}
+
+impl From<&StoredStats> for RunId {
+    fn from(stats: &StoredStats) -> RunId {
+        RunId {
+            start_time: stats.start_time,
+            duration: stats.duration,
+            distance: stats.distance,
+            nb_steps: stats.nb_steps,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    /// How long to wait for each record.
+    pub timeout: Duration,
+    /// How many requests may go unanswered over the whole sync.
+    pub retries: u32,
+    /// Gives up with [`Error::Timeout`] once the sync takes longer, whatever the retries left.
+    pub deadline: Duration,
+    /// The runs stored by a previous sync, which are walked past without being returned again.
+    pub synced: HashSet<RunId>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions {
+        SyncOptions {
+            timeout: Duration::from_secs(1),
+            retries: 5,
+            deadline: Duration::from_secs(60),
+            synced: HashSet::new(),
+        }
+    }
+}
+
+/// Reported after each record received.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SyncProgress {
+    pub fetched: usize,
+    pub skipped: usize,
+}
+
+/// What a sync got through, even when it was cut short.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// The runs which weren't synced yet, from the latest to the oldest.
+    pub records: Vec<StoredStats>,
+    /// How many runs were already synced.
+    pub skipped: usize,
+    /// Why the sync stopped before the oldest run.
+    pub error: Option<Error>,
+}
+
+impl SyncReport {
+    /// Whether every run stored on the treadmill was walked through.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Fetches the runs stored on the treadmill from the latest to the oldest, leaving them there.
+///
+/// The receiver is expected to carry stored stats answers only, any other response is ignored.
+pub fn sync_history(
+    sender: &WalkingPadSender,
+    receiver: &Receiver<Response>,
+    options: &SyncOptions,
+    progress: &mut dyn FnMut(SyncProgress),
+) -> SyncReport {
+    let deadline = Instant::now() + options.deadline;
+    let mut report = SyncReport::default();
+    let mut seen = HashSet::new();
+    let mut retries = options.retries;
+    let mut request = request::get::latest_stored_stats();
+
+    loop {
+        // A late answer to an earlier request would be mistaken for this one's
+        while receiver.try_recv().is_ok() {}
+
+        if let Err(err) = sender.send(request.clone()) {
+            report.error = Some(err.into());
+            return report;
+        }
+
+        let stats = match receive(receiver, options.timeout, deadline) {
+            Ok(stats) => stats,
+            Err(Error::Timeout) if retries > 0 && Instant::now() < deadline => {
+                log::warn!("No answer to {:?}, retrying", request);
+                retries -= 1;
+                continue;
+            }
+            Err(err) => {
+                report.error = Some(err);
+                return report;
+            }
+        };
+
+        // An empty history is answered with a blank record
+        if stats.start_time == 0 && stats.duration.is_zero() {
+            return report;
+        }
+
+        let id = RunId::from(&stats);
+        // The records are chained, going around in circles would never end, nor tell whether
+        // some runs were left out of the loop
+        if !seen.insert(id.clone()) {
+            report.error = Some(Error::CorruptHistory);
+            return report;
+        }
+
+        let next_id = stats.next_id;
+        match options.synced.contains(&id) {
+            true => report.skipped += 1,
+            false => report.records.push(stats),
+        }
+
+        progress(SyncProgress {
+            fetched: report.records.len(),
+            skipped: report.skipped,
+        });
+
+        match next_id {
+            Some(next_id) => request = request::get::stored_stats(next_id),
+            None => return report,
+        }
+    }
+}
+
+/// Clears the runs stored on the treadmill, once the records of a complete sync are safely
+/// stored.
+///
+/// Runs finishing between the sync and the clearing are lost.
+pub fn clear_history(sender: &WalkingPadSender, report: &SyncReport) -> Result<()> {
+    if !report.is_complete() {
+        return Err(Error::IncompleteSync);
+    }
+
+    Ok(sender.send(request::clear_stats())?)
+}
+
+fn receive(
+    receiver: &Receiver<Response>,
+    timeout: Duration,
+    deadline: Instant,
+) -> Result<StoredStats> {
+    let deadline = deadline.min(Instant::now() + timeout);
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        match receiver.recv_timeout(timeout) {
+            Ok(Response::StoredStats(stats)) => return Ok(stats),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::ConnectionClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use walkingpad_protocol::Request;
+
+    fn stats(start_time: u32, next_id: Option<u8>) -> StoredStats {
+        StoredStats {
+            current_time: 1000,
+            start_time,
+            duration: Duration::from_secs(60),
+            distance: 100,
+            nb_steps: 100,
+            next_id,
+        }
+    }
+
+    /// Plays a treadmill storing the runs, the latest first, ignoring the first request.
+    fn play_pad(runs: Vec<StoredStats>) -> (WalkingPadSender, Receiver<Response>) {
+        let (sender, requests) = mpsc::sync_channel::<Request>(10);
+        let (responses_in, responses) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for request in requests.iter().skip(1) {
+                let stats = if request == request::get::latest_stored_stats() {
+                    runs[0].clone()
+                } else if request == request::clear_stats() {
+                    continue;
+                } else {
+                    let id = request.as_bytes()[3] as usize;
+                    runs[id].clone()
+                };
+
+                if responses_in.send(Response::StoredStats(stats)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        (sender, responses)
+    }
+
+    #[test]
+    fn test() {
+        let runs = vec![stats(30, Some(1)), stats(20, Some(2)), stats(10, None)];
+        let (sender, receiver) = play_pad(runs.clone());
+
+        let options = SyncOptions {
+            timeout: Duration::from_millis(100),
+            synced: HashSet::from([RunId::from(&runs[1])]),
+            ..SyncOptions::default()
+        };
+        let mut reports = vec![];
+        let report = sync_history(&sender, &receiver, &options, &mut |p| reports.push(p));
+
+        assert!(report.is_complete());
+        assert_eq!(report.records, [runs[0].clone(), runs[2].clone()]);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(
+            reports.last(),
+            Some(&SyncProgress {
+                fetched: 2,
+                skipped: 1
+            })
+        );
+        assert!(clear_history(&sender, &report).is_ok());
+
+        let (sender, receiver) = play_pad(runs);
+        let options = SyncOptions {
+            timeout: Duration::from_millis(10),
+            retries: 0,
+            ..SyncOptions::default()
+        };
+        let report = sync_history(&sender, &receiver, &options, &mut |_| {});
+        assert!(matches!(report.error, Some(Error::Timeout)));
+        assert!(matches!(
+            clear_history(&sender, &report),
+            Err(Error::IncompleteSync)
+        ));
+
+        let runs = vec![stats(30, Some(1)), stats(20, Some(2)), stats(10, Some(1))];
+        let (sender, receiver) = play_pad(runs.clone());
+        let options = SyncOptions {
+            timeout: Duration::from_millis(100),
+            ..SyncOptions::default()
+        };
+        let report = sync_history(&sender, &receiver, &options, &mut |_| {});
+        assert_eq!(report.records, runs);
+        assert!(matches!(report.error, Some(Error::CorruptHistory)));
+        assert!(!report.is_complete());
+        assert!(clear_history(&sender, &report).is_err());
+    }
+}
@@ -1,33 +1,134 @@
-use futures::Stream;
-use once_cell::sync::OnceCell;
+mod ble;
+mod bridge;
+mod capture;
+mod client;
+mod discovery;
+mod events;
+mod ftms;
+mod history;
+mod scheduler;
+mod session;
+#[cfg(feature = "sim")]
+mod sim;
+mod transport;
+mod treadmill;
+mod verified;
+mod watch;
+
+pub use ble::BleTransport;
+pub use bridge::{Bridge, BridgeOptions, TcpTransport};
+pub use capture::{read_capture, CaptureRecord, CaptureTransport, Captured, ReplayTransport};
+pub use client::{
+    Backend, MalformedFrame, QueryOptions, Received, ReceivedStream, ResponseStream, WalkingPad,
+};
+pub use discovery::{
+    scan, BDAddr, ConnectOptions, DeviceStream, DiscoveredDevice, Model, ScanOptions, Target,
+};
+pub use events::{Event, EventStream, STEPS_MILESTONE};
+pub use history::{clear_history, sync_history, RunId, SyncOptions, SyncProgress, SyncReport};
+pub use scheduler::{SchedulerOptions, WriteType};
+pub use session::{ConnectionEvent, PollingOptions, ReconnectPolicy};
+#[cfg(feature = "sim")]
+pub use sim::SimTransport;
+pub use transport::{memory_transport, Frame, FrameStream, MemoryPeer, MemoryTransport, Transport};
+pub use treadmill::{
+    connect_treadmill, connect_treadmill_with, FtmsTreadmill, NativeTreadmill, Treadmill,
+};
+pub use verified::VerifyOptions;
+#[cfg(feature = "sim")]
+pub use walkingpad_sim::Simulator;
+pub use watch::Watch;
+
+use session::Shared;
+use uuid::Uuid;
 use walkingpad_protocol::request;
 use walkingpad_protocol::response::StoredStats;
 use walkingpad_protocol::{Request, Response};
 
 use std::fmt;
 use std::fmt::Display;
-use std::pin::Pin;
-use std::sync::mpsc::RecvTimeoutError;
-use std::time::{Duration, Instant};
-
-use btleplug::api::bleuuid::uuid_from_u16;
-use btleplug::api::Peripheral as _;
-use btleplug::api::{Central, ScanFilter, WriteType};
-use btleplug::api::{Manager as _, ValueNotification};
-use btleplug::platform::{Adapter, Manager, Peripheral};
-use futures::future;
-use futures::stream::StreamExt;
-use uuid::Uuid;
+use std::io;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
+    /// The link couldn't be set up, for a reason of its own rather than an underlying error.
     ConnectionError(String),
     ConnectionClosed,
-    ConnectionAlreadyEstablished,
     NoWalkingPadFound,
     NoAdapters,
+    /// The Bluetooth adapters couldn't be queried or scan, e.g. while they're powered off.
+    AdapterUnavailable(btleplug::Error),
+    /// The treadmill doesn't offer a characteristic its protocol relies on.
+    CharacteristicMissing {
+        uuid: Uuid,
+    },
+    /// The transport couldn't write a frame to the treadmill.
+    WriteFailed(Box<dyn std::error::Error + Send + Sync>),
+    /// Any other failure of the Bluetooth stack.
+    Bluetooth(btleplug::Error),
+    Io(std::io::Error),
+    /// A frame doesn't follow the treadmill's protocol.
+    Protocol(walkingpad_protocol::Error),
+    Unsupported,
+    Timeout,
+    /// The treadmill never reported the change requested.
+    NotApplied(Request),
+    /// Clearing the stored runs would lose those the sync didn't get to.
+    IncompleteSync,
+    /// The stored runs chain back to one already walked through.
+    CorruptHistory,
+    /// The URI doesn't describe a treadmill, see [`Target`].
+    InvalidUri(String),
+    /// The capture can't be replayed.
+    InvalidCapture(String),
+}
+
+impl Error {
+    /// Whether trying again, e.g. by reconnecting, may succeed without anyone stepping in.
+    pub fn is_retryable(&self) -> bool {
+        use Error::*;
+
+        match self {
+            ConnectionClosed
+            | NoWalkingPadFound
+            | AdapterUnavailable(_)
+            | WriteFailed(_)
+            | Timeout
+            | NotApplied(_) => true,
+            Bluetooth(err) => !matches!(
+                err,
+                btleplug::Error::PermissionDenied
+                    | btleplug::Error::NotSupported(_)
+                    | btleplug::Error::Uuid(_)
+                    | btleplug::Error::InvalidBDAddr(_)
+            ),
+            Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::HostUnreachable
+                    | io::ErrorKind::NetworkUnreachable
+            ),
+            ConnectionError(_)
+            | NoAdapters
+            | CharacteristicMissing { .. }
+            | Protocol(_)
+            | Unsupported
+            | IncompleteSync
+            | CorruptHistory
+            | InvalidUri(_)
+            | InvalidCapture(_) => false,
+        }
+    }
 }
 
 impl Display for Error {
@@ -37,24 +138,54 @@ impl Display for Error {
         match self {
             ConnectionError(inner) => write!(f, "Error connecting to the WalkingPad: {}", inner),
             ConnectionClosed => write!(f, "Connection was closed"),
-            ConnectionAlreadyEstablished => write!(f, "Connection was already established"),
             NoWalkingPadFound => write!(f, "No WalkingPad found"),
             NoAdapters => write!(f, "No bluetooth adapters found"),
+            AdapterUnavailable(err) => write!(f, "The bluetooth adapter is unavailable: {}", err),
+            CharacteristicMissing { uuid } => {
+                write!(f, "The treadmill has no {} characteristic", uuid)
+            }
+            WriteFailed(err) => write!(f, "Writing to the treadmill failed: {}", err),
+            Bluetooth(err) => write!(f, "Bluetooth error: {}", err),
+            Io(err) => write!(f, "I/O error: {}", err),
+            Protocol(err) => write!(f, "Protocol error: {}", err),
+            Unsupported => write!(f, "Not supported by this treadmill"),
+            Timeout => write!(f, "The treadmill didn't answer in time"),
+            NotApplied(request) => write!(f, "The treadmill didn't apply {:?}", request),
+            IncompleteSync => write!(f, "The stored runs weren't all synced"),
+            CorruptHistory => write!(f, "The stored runs are chained in a loop"),
+            InvalidUri(inner) => write!(f, "Invalid treadmill URI {}", inner),
+            InvalidCapture(inner) => write!(f, "Invalid capture: {}", inner),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::AdapterUnavailable(err) | Error::Bluetooth(err) => Some(err),
+            Error::WriteFailed(err) => Some(err.as_ref()),
+            Error::Io(err) => Some(err),
+            Error::Protocol(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl From<btleplug::Error> for Error {
     fn from(err: btleplug::Error) -> Self {
-        Error::ConnectionError(format!("{}", err))
+        Error::Bluetooth(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::ConnectionError(format!("{}", err))
+impl From<walkingpad_protocol::Error> for Error {
+    fn from(err: walkingpad_protocol::Error) -> Self {
+        Error::Protocol(err)
     }
 }
 
@@ -64,11 +195,23 @@ impl From<std::sync::mpsc::SendError<Request>> for Error {
     }
 }
 
+/// Clears the runs stored on the treadmill once they are all gathered, see [`sync_history`] to
+/// keep them until they are safely stored.
 pub fn gather_run_statistics(
     sender: &WalkingPadSender,
     receiver: &WalkingPadReceiver,
 ) -> (Vec<StoredStats>, Option<Error>) {
-    let (mut stats, err) = gather_run_statistics_impl(sender, receiver);
+    gather_run_statistics_with(sender, receiver, QueryOptions::default())
+}
+
+/// Like [`gather_run_statistics`], giving up with [`Error::Timeout`] once the treadmill stops
+/// answering for longer than the options allow.
+pub fn gather_run_statistics_with(
+    sender: &WalkingPadSender,
+    receiver: &WalkingPadReceiver,
+    options: QueryOptions,
+) -> (Vec<StoredStats>, Option<Error>) {
+    let (mut stats, err) = gather_run_statistics_impl(sender, receiver, options);
     stats.sort_by_key(|s| s.start_time);
     stats.dedup_by_key(|s| s.start_time);
 
@@ -78,24 +221,32 @@ pub fn gather_run_statistics(
 fn gather_run_statistics_impl(
     sender: &WalkingPadSender,
     receiver: &WalkingPadReceiver,
+    options: QueryOptions,
 ) -> (Vec<StoredStats>, Option<Error>) {
     let mut stats = vec![];
+    let mut timeouts = 0;
 
     loop {
         if let Err(err) = sender.send(request::get::latest_stored_stats()) {
             return (stats, Some(err.into()));
         }
 
-        let response = match receiver.recv_timeout(Duration::from_secs(1)) {
+        let response = match receiver.recv_timeout(options.timeout) {
             Ok(r) => r,
             Err(RecvTimeoutError::Timeout) => {
                 log::warn!("recv() timeout");
+                timeouts += 1;
+                if timeouts > options.retries {
+                    return (stats, Some(Error::Timeout));
+                }
                 continue;
             }
             Err(_) => return (stats, Some(Error::ConnectionClosed)),
         };
 
         if let Response::StoredStats(s) = response {
+            timeouts = 0;
+
             if s.start_time == 0 && s.duration.is_zero() {
                 return (stats, None);
             }
@@ -124,169 +275,82 @@ pub type WalkingPadReceiver = std::sync::mpsc::Receiver<Response>;
 
 pub type WalkingPadSender = std::sync::mpsc::SyncSender<Request>;
 
-static CONNECTION_FLAG: OnceCell<()> = OnceCell::new();
-
+/// Connects to a WalkingPad from a dedicated thread running its own runtime, for applications
+/// which aren't async. See [`WalkingPad`] to connect from an existing tokio runtime instead.
+///
+/// The connection is closed once either the sender or the receiver is dropped.
 pub fn connect() -> Result<(WalkingPadSender, WalkingPadReceiver)> {
-    if CONNECTION_FLAG.get().is_some() {
-        return Err(Error::ConnectionAlreadyEstablished);
-    }
+    connect_with(ConnectOptions::default())
+}
 
+/// Like [`connect`], targeting the treadmill described by the options.
+pub fn connect_with(options: ConnectOptions) -> Result<(WalkingPadSender, WalkingPadReceiver)> {
+    let (sender, received, _, _) = connect_impl(options)?;
     let (receiver_in, receiver_out) = std::sync::mpsc::channel();
-    let (sender_in, sender_out) = std::sync::mpsc::sync_channel::<Request>(10);
-    let (init_in, init_out) = tokio::sync::oneshot::channel::<Result<()>>();
 
-    let _t = std::thread::spawn(move || {
-        macro_rules! unwrap_or_return {
-            ( $e:expr ) => {
-                match $e {
-                    Ok(x) => x,
-                    Err(err) => {
-                        init_in.send(Err(err.into())).unwrap();
-                        return;
-                    }
+    std::thread::spawn(move || {
+        while let Ok(received) = received.recv() {
+            if let Some(response) = received.into_response() {
+                if receiver_in.send(response).is_err() {
+                    break;
                 }
-            };
+            }
         }
+    });
 
-        let rt = unwrap_or_return!(tokio::runtime::Builder::new_current_thread()
-            .enable_time()
-            .build());
-
-        let walkingpad = unwrap_or_return!(rt.block_on(init_walkingpad()));
-
-        let mut notification_stream =
-            unwrap_or_return!(rt.block_on(notification_stream(walkingpad.clone())));
-
-        let write_characteristic = {
-            let characteristics = walkingpad.characteristics();
-
-            const WRITE_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0xfe02);
-
-            unwrap_or_return!(characteristics
-                .iter()
-                .find(|c| c.uuid == WRITE_CHARACTERISTIC_UUID)
-                .ok_or_else(|| Error::ConnectionError("No write characteristic found".to_string())))
-            .clone()
-        };
-
-        let sender = async move {
-            let mut last_write = Instant::now();
-
-            while let Ok(command) = async { sender_out.recv() }.await {
-                const MIN_WAIT: Duration = Duration::from_millis(300);
-
-                let time_since_write = last_write.elapsed();
-                if time_since_write < MIN_WAIT {
-                    tokio::time::sleep(MIN_WAIT - time_since_write).await;
-                }
-
-                let result = walkingpad
-                    .write(
-                        &write_characteristic,
-                        command.as_bytes(),
-                        WriteType::WithoutResponse,
-                    )
-                    .await;
+    Ok((sender, receiver_out))
+}
 
-                if let Err(err) = result {
-                    log::error!("WalkingPad write failed: {}", err);
-                    break;
+fn connect_impl(
+    options: ConnectOptions,
+) -> Result<(WalkingPadSender, Receiver<Received>, Backend, Shared)> {
+    let (receiver_in, receiver_out) = std::sync::mpsc::channel();
+    let (sender_in, sender_out) = std::sync::mpsc::sync_channel::<Request>(10);
+    let (init_in, init_out) = tokio::sync::oneshot::channel::<Result<Backend>>();
+    let shared = Shared::default();
+
+    {
+        let shared = shared.clone();
+
+        std::thread::spawn(move || {
+            let rt = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(err) => {
+                    init_in.send(Err(err.into())).unwrap();
+                    return;
                 }
+            };
 
-                last_write = Instant::now();
-            }
-        };
+            rt.block_on(async move {
+                let walkingpad = match WalkingPad::connect_with(&options).await {
+                    Ok(walkingpad) => walkingpad,
+                    Err(err) => {
+                        init_in.send(Err(err)).unwrap();
+                        return;
+                    }
+                };
 
-        let receiver = async move {
-            while let Some(data) = notification_stream.next().await {
-                match Response::parse(data.value.as_slice()) {
-                    Ok(response) => {
-                        if receiver_in.send(response).is_err() {
+                // The blocking channel can't be awaited, so it gets forwarded from its own thread
+                let (commands_in, commands_out) = tokio::sync::mpsc::channel::<Request>(1);
+                std::thread::spawn(move || {
+                    while let Ok(command) = sender_out.recv() {
+                        if commands_in.blocking_send(command).is_err() {
                             break;
                         }
                     }
-                    Err(err) => log::error!("malformed response: {}: `{:?}`", err, data),
-                }
-            }
-        };
-
-        init_in.send(Ok(())).unwrap();
-
-        rt.block_on(async { future::join(sender, receiver).await });
-    });
-
-    init_out.blocking_recv().unwrap()?;
+                });
 
-    CONNECTION_FLAG.set(()).unwrap();
+                init_in.send(Ok(walkingpad.backend)).unwrap();
 
-    Ok((sender_in, receiver_out))
-}
-
-async fn init_walkingpad() -> Result<Peripheral> {
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let main_adapter = adapters.first().ok_or(Error::NoAdapters)?;
-
-    let walkingpad = discover_walkingpad(main_adapter).await?;
-    walkingpad.connect().await?;
-    walkingpad.discover_services().await?;
-
-    Ok(walkingpad)
-}
-
-type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
-
-async fn notification_stream(walkingpad: Peripheral) -> Result<NotificationStream> {
-    let characteristics = walkingpad.characteristics();
-
-    const READ_CHARACTERISTIC_UUID: Uuid = uuid_from_u16(0xfe01);
-
-    let read_characteristic = characteristics
-        .iter()
-        .find(|c| c.uuid == READ_CHARACTERISTIC_UUID)
-        .ok_or_else(|| Error::ConnectionError("No read characteristic found".to_string()))?;
-
-    walkingpad.subscribe(read_characteristic).await?;
-
-    let stream = walkingpad.notifications().await?;
-
-    Ok(stream)
-}
-
-async fn discover_walkingpad(adapter: &Adapter) -> Result<Peripheral> {
-    const SERVICE_UUID: Uuid = uuid_from_u16(0xfe00);
-    let filter = ScanFilter {
-        services: vec![SERVICE_UUID],
-    };
-    adapter.start_scan(filter).await?;
-
-    // TODO: Is this necessary?
-    tokio::time::sleep(Duration::from_millis(250)).await;
-
-    let peripherals = match adapter.peripherals().await {
-        Ok(p) => p,
-        Err(err) => {
-            adapter.stop_scan().await?;
-            return Err(Error::from(err));
-        }
-    };
-
-    for peripheral in peripherals {
-        let properties = match peripheral.properties().await {
-            Ok(p) => p,
-            Err(err) => {
-                adapter.stop_scan().await?;
-                return Err(Error::from(err));
-            }
-        };
-
-        if let Some(properties) = properties {
-            let names = properties.local_name;
-            if names.iter().any(|name| name == "WalkingPad") {
-                return Ok(peripheral);
-            }
-        };
+                session::run(walkingpad, options, commands_out, receiver_in, shared).await;
+            });
+        });
     }
 
-    Err(Error::NoWalkingPadFound)
+    let backend = init_out.blocking_recv().unwrap()?;
+
+    Ok((sender_in, receiver_out, backend, shared))
 }
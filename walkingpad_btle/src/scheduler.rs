@@ -0,0 +1,174 @@
+//! Orders the commands waiting to be written to the treadmill.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use walkingpad_protocol::{request, Request, Speed};
+
+pub use btleplug::api::WriteType;
+
+/// How commands are written to the treadmill.
+#[derive(Clone, Debug)]
+pub struct SchedulerOptions {
+    /// The minimum delay between two writes, the WalkingPad ignores writes coming too close to
+    /// each other.
+    pub min_interval: Duration,
+    /// Overrides the write type of the treadmill's protocol, e.g. to have a WalkingPad
+    /// acknowledge every write.
+    pub write_type: Option<WriteType>,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> SchedulerOptions {
+        SchedulerOptions {
+            min_interval: Duration::from_millis(300),
+            write_type: None,
+        }
+    }
+}
+
+/// The commands waiting to be written, where a stop jumps the queue and a command replaces the
+/// queued one it supersedes.
+///
+/// Once full, the oldest command other than a stop makes room for the new one, since holding
+/// commands back in the channel would hold back a stop behind them too.
+#[derive(Debug)]
+pub(crate) struct CommandQueue {
+    commands: VecDeque<Request>,
+    capacity: usize,
+}
+
+impl CommandQueue {
+    pub(crate) fn new(capacity: usize) -> CommandQueue {
+        CommandQueue {
+            commands: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn push(&mut self, command: Request) {
+        if command == request::stop() {
+            // Moving the belt is pointless once stopped
+            let any_speed = request::set::speed(Speed::from_hm_per_hour(0));
+            self.commands
+                .retain(|c| !command.supersedes(c) && !any_speed.supersedes(c));
+            self.make_room();
+            self.commands.push_front(command);
+            return;
+        }
+
+        // The superseded command keeps its place in line
+        match self.commands.iter_mut().find(|c| command.supersedes(c)) {
+            Some(queued) => *queued = command,
+            None => {
+                self.make_room();
+                self.commands.push_back(command);
+            }
+        }
+    }
+
+    /// Puts back a command which couldn't be written, unless it has been superseded meanwhile.
+    pub(crate) fn push_front(&mut self, command: Request) {
+        if !self.commands.iter().any(|c| c.supersedes(&command)) {
+            self.commands.push_front(command);
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Request> {
+        self.commands.pop_front()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Drops every command but a stop, which is never given up on.
+    pub(crate) fn clear(&mut self) {
+        let stop = request::stop();
+        self.commands.retain(|c| *c == stop);
+    }
+
+    /// Stops are never dropped.
+    fn make_room(&mut self) {
+        if self.commands.len() < self.capacity {
+            return;
+        }
+
+        let stop = request::stop();
+        if let Some(i) = self.commands.iter().position(|c| *c != stop) {
+            let command = self.commands.remove(i);
+            log::warn!("Too many queued commands, dropping {:?}", command);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use walkingpad_protocol::Mode;
+
+    fn speed(hm_per_hour: u8) -> Request {
+        request::set::speed(Speed::from_hm_per_hour(hm_per_hour))
+    }
+
+    #[test]
+    fn test() {
+        let mut queue = CommandQueue::new(4);
+
+        queue.push(request::get::state());
+        queue.push(speed(20));
+        queue.push(request::get::state());
+        queue.push(request::set::mode(Mode::Manual));
+        queue.push(speed(25));
+        queue.push(speed(30));
+
+        assert_eq!(queue.pop(), Some(request::get::state()));
+        assert_eq!(queue.pop(), Some(speed(30)));
+        assert_eq!(queue.pop(), Some(request::set::mode(Mode::Manual)));
+        assert!(queue.is_empty());
+
+        queue.push(request::start());
+        queue.push(request::get::state());
+        queue.push(speed(30));
+        queue.push(request::stop());
+
+        assert_eq!(queue.pop(), Some(request::stop()));
+        assert_eq!(queue.pop(), Some(request::get::state()));
+        assert!(queue.is_empty());
+
+        queue.push(speed(30));
+        queue.push_front(speed(20));
+        assert_eq!(queue.pop(), Some(speed(30)));
+        assert!(queue.is_empty());
+
+        queue.push(speed(30));
+        queue.push(request::stop());
+        queue.clear();
+        assert_eq!(queue.pop(), Some(request::stop()));
+        assert!(queue.is_empty());
+
+        // A start coming after a stop doesn't take its place
+        queue.push(request::stop());
+        queue.push(request::start());
+        assert_eq!(queue.pop(), Some(request::stop()));
+        assert_eq!(queue.pop(), Some(request::start()));
+        assert!(queue.is_empty());
+
+        for id in 0..4 {
+            queue.push(request::get::stored_stats(id));
+        }
+        // Superseding a queued command doesn't take more room
+        queue.push(request::get::stored_stats(0));
+
+        // A stop gets in however full the queue is
+        queue.push(request::stop());
+        assert_eq!(queue.pop(), Some(request::stop()));
+        assert_eq!(queue.pop(), Some(request::get::stored_stats(1)));
+
+        // The oldest command makes room
+        for id in 4..7 {
+            queue.push(request::get::stored_stats(id));
+        }
+        assert_eq!(queue.pop(), Some(request::get::stored_stats(3)));
+    }
+}
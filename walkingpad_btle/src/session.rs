@@ -0,0 +1,522 @@
+//! Drives the connection of the blocking API, reconnecting when the link is lost.
+
+use std::cell::Cell;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use futures::future::{self, Either, FutureExt};
+use futures::stream::StreamExt;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::MotorState;
+use walkingpad_protocol::{Request, Response};
+
+use crate::scheduler::CommandQueue;
+use crate::{Backend, ConnectOptions, Received, Target, WalkingPad};
+
+/// Past this many queued commands, the oldest one other than a stop is dropped.
+const MAX_QUEUED_COMMANDS: usize = 10;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionEvent {
+    Connected,
+    Disconnected,
+}
+
+/// How to reconnect once the link to the treadmill is lost.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// The delay before the first attempt, doubled after each failed attempt.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Gives up after this many failed attempts in a row, never when unset. Errors which aren't
+    /// [retryable](crate::Error::is_retryable) give up right away.
+    pub max_attempts: Option<u32>,
+    /// Writes the commands which couldn't be delivered once reconnected, instead of dropping
+    /// them. A stop is written once reconnected either way.
+    pub replay_commands: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+            replay_commands: false,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+}
+
+/// How often the session asks for the treadmill's state, so that applications don't have to.
+///
+/// Polls are only written when no command is waiting, and not at all while the stored stats are
+/// being gathered.
+#[derive(Clone, Debug)]
+pub struct PollingOptions {
+    /// While the belt is running.
+    pub running_interval: Duration,
+    /// While the belt is stopped.
+    pub idle_interval: Duration,
+}
+
+impl Default for PollingOptions {
+    fn default() -> PollingOptions {
+        PollingOptions {
+            running_interval: Duration::from_secs(1),
+            idle_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl PollingOptions {
+    fn interval(&self, is_running: bool) -> Duration {
+        if is_running {
+            self.running_interval
+        } else {
+            self.idle_interval
+        }
+    }
+}
+
+pub(crate) type EventSubscribers = Arc<Mutex<Vec<Sender<ConnectionEvent>>>>;
+
+/// What the application's side of the blocking API shares with the session.
+#[derive(Clone, Default)]
+pub(crate) struct Shared {
+    pub(crate) events: EventSubscribers,
+    pub(crate) is_polling_paused: Arc<AtomicBool>,
+}
+
+fn notify(subscribers: &EventSubscribers, event: ConnectionEvent) {
+    subscribers
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|s| s.send(event).is_ok());
+}
+
+enum End {
+    /// The application is done with the connection.
+    Closed,
+    LinkLost,
+}
+
+/// Forwards the commands to the treadmill and its responses back until the application hangs up,
+/// reconnecting along the way if the options allow it.
+pub(crate) async fn run(
+    mut walkingpad: WalkingPad,
+    options: ConnectOptions,
+    mut commands: mpsc::Receiver<Request>,
+    responses: Sender<Received>,
+    shared: Shared,
+) {
+    // Reconnections must get back to the same treadmill
+    let options = match walkingpad.address() {
+        Some(address) => ConnectOptions {
+            target: Target::Address(address),
+            ..options
+        },
+        None => options,
+    };
+    let mut queue = CommandQueue::new(MAX_QUEUED_COMMANDS);
+    // The sequence goes on across reconnections
+    let sequence = Cell::new(0);
+
+    loop {
+        notify(&shared.events, ConnectionEvent::Connected);
+
+        let end = serve(
+            &walkingpad,
+            &options,
+            &mut commands,
+            &mut queue,
+            &responses,
+            &sequence,
+            &shared,
+        )
+        .await;
+
+        if let Err(err) = walkingpad.disconnect().await {
+            log::warn!("WalkingPad disconnection failed: {}", err);
+        }
+        notify(&shared.events, ConnectionEvent::Disconnected);
+
+        let (End::LinkLost, Some(policy)) = (end, &options.reconnect) else {
+            return;
+        };
+
+        log::warn!("Lost the link to the WalkingPad, reconnecting");
+        if !policy.replay_commands {
+            queue.clear();
+        }
+
+        walkingpad = match reconnect(&options, policy, &mut commands, &mut queue).await {
+            Some(walkingpad) => walkingpad,
+            None => return,
+        };
+    }
+}
+
+async fn serve(
+    walkingpad: &WalkingPad,
+    options: &ConnectOptions,
+    commands: &mut mpsc::Receiver<Request>,
+    queue: &mut CommandQueue,
+    responses_out: &Sender<Received>,
+    sequence: &Cell<u64>,
+    shared: &Shared,
+) -> End {
+    let mut responses = match walkingpad.received().await {
+        Ok(responses) => responses,
+        Err(err) => {
+            log::error!("WalkingPad subscription failed: {}", err);
+            return End::LinkLost;
+        }
+    };
+
+    // FTMS treadmills notify their state on their own
+    let polling = match walkingpad.backend {
+        Backend::WalkingPad => options.polling.as_ref(),
+        Backend::Ftms => None,
+    };
+    let is_running = Cell::new(false);
+    let running_changed = Notify::new();
+
+    let sender = async {
+        let mut is_closed = false;
+        let mut is_poll_due = false;
+        let mut last_poll = None;
+
+        loop {
+            // Whatever came in meanwhile gets a chance to preempt or supersede the queue
+            while let Ok(command) = commands.try_recv() {
+                queue.push(command);
+            }
+
+            if queue.is_empty() && is_closed {
+                return End::Closed;
+            }
+
+            if queue.is_empty() && !is_poll_due {
+                // The interval follows the belt, even between two polls
+                let last = last_poll;
+                let poll = async {
+                    let Some(polling) = polling else {
+                        return future::pending().await;
+                    };
+
+                    loop {
+                        let interval = polling.interval(is_running.get());
+                        let deadline = last.map_or_else(Instant::now, |t| t + interval);
+                        let sleep = tokio::time::sleep_until(deadline);
+
+                        if let Either::Left(_) =
+                            future::select(pin!(sleep), pin!(running_changed.notified())).await
+                        {
+                            return;
+                        }
+                    }
+                };
+
+                match future::select(pin!(commands.recv()), pin!(poll)).await {
+                    Either::Left((Some(command), _)) => queue.push(command),
+                    // The queue is flushed before hanging up, the last command may be a stop
+                    Either::Left((None, _)) => is_closed = true,
+                    Either::Right(_) => {
+                        is_poll_due = !shared.is_polling_paused.load(Ordering::SeqCst);
+                        if !is_poll_due {
+                            last_poll = Some(Instant::now());
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if !is_closed {
+                match future::select(pin!(walkingpad.write_slot()), pin!(commands.recv())).await {
+                    Either::Left(_) => {}
+                    Either::Right((Some(command), _)) => {
+                        queue.push(command);
+                        continue;
+                    }
+                    Either::Right((None, _)) => is_closed = true,
+                }
+            }
+
+            // Polls only get the write slots no command wants
+            let command = queue.pop().unwrap_or_else(request::get::state);
+
+            if command == request::get::state() {
+                is_poll_due = false;
+                last_poll = Some(Instant::now());
+            }
+
+            if let Err(err) = walkingpad.send(command.clone()).await {
+                log::error!("WalkingPad write failed: {}", err);
+                queue.push_front(command);
+                return End::LinkLost;
+            }
+        }
+    };
+
+    let receiver = async {
+        while let Some(mut received) = responses.next().await {
+            received.sequence = sequence.replace(sequence.get() + 1);
+
+            if let Ok(Response::State(state)) = &received.item {
+                let was_running = is_running.replace(state.motor_state == MotorState::Running);
+                if was_running != is_running.get() {
+                    running_changed.notify_waiters();
+                }
+            }
+
+            if responses_out.send(received).is_err() {
+                return End::Closed;
+            }
+        }
+        End::LinkLost
+    };
+
+    let link = async {
+        walkingpad.link_lost().await;
+        End::LinkLost
+    };
+
+    let (end, _, _) = future::select_all([
+        sender.boxed_local(),
+        receiver.boxed_local(),
+        link.boxed_local(),
+    ])
+    .await;
+
+    end
+}
+
+/// Returns `None` once the policy gives up or the application hangs up.
+async fn reconnect(
+    options: &ConnectOptions,
+    policy: &ReconnectPolicy,
+    commands: &mut mpsc::Receiver<Request>,
+    queue: &mut CommandQueue,
+) -> Option<WalkingPad> {
+    let mut attempt = 0;
+
+    loop {
+        let connection = async {
+            tokio::time::sleep(policy.delay(attempt)).await;
+            WalkingPad::connect_with(options).await
+        };
+
+        // Commands keep coming in meanwhile, so they're held for replay or dropped, but for a
+        // stop
+        let hold = async {
+            while let Some(command) = commands.recv().await {
+                if policy.replay_commands || command == request::stop() {
+                    queue.push(command);
+                }
+            }
+        };
+
+        match future::select(pin!(connection), pin!(hold)).await {
+            Either::Left((Ok(walkingpad), _)) => {
+                log::info!("Reconnected to the WalkingPad");
+                return Some(walkingpad);
+            }
+            // e.g. a treadmill lacking a characteristic won't grow it back
+            Either::Left((Err(err), _)) if !err.is_retryable() => {
+                log::error!("Giving up reconnecting: {}", err);
+                return None;
+            }
+            Either::Left((Err(err), _)) => {
+                log::warn!("Reconnection attempt {} failed: {}", attempt + 1, err)
+            }
+            Either::Right(_) => return None,
+        }
+
+        attempt += 1;
+
+        if policy.max_attempts.is_some_and(|max| attempt >= max) {
+            log::error!("Giving up reconnecting after {} attempts", attempt);
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_transport;
+    use btleplug::api::bleuuid::uuid_from_u16;
+    use walkingpad_protocol::fixtures::STATE;
+    use walkingpad_protocol::response::State;
+    use walkingpad_protocol::Speed;
+
+    fn speed(hm_per_hour: u8) -> Request {
+        request::set::speed(Speed::from_hm_per_hour(hm_per_hour))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let (commands_in, commands_out) = mpsc::channel(10);
+        let (responses_in, _responses_out) = std::sync::mpsc::channel();
+        let shared = Shared::default();
+        let (events_in, events_out) = std::sync::mpsc::channel();
+        shared
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(events_in);
+
+        for command in [
+            speed(20),
+            request::get::state(),
+            speed(25),
+            speed(30),
+            request::stop(),
+        ] {
+            commands_in.send(command).await.unwrap();
+        }
+        drop(commands_in);
+
+        run(
+            walkingpad,
+            ConnectOptions::default(),
+            commands_out,
+            responses_in,
+            shared,
+        )
+        .await;
+
+        assert_eq!(
+            peer.next_write().await,
+            Some(request::stop().as_bytes().to_vec())
+        );
+        assert_eq!(
+            peer.next_write().await,
+            Some(request::get::state().as_bytes().to_vec())
+        );
+        assert_eq!(peer.next_write().await, None);
+
+        let events: Vec<_> = events_out.try_iter().collect();
+        assert_eq!(
+            events,
+            [ConnectionEvent::Connected, ConnectionEvent::Disconnected]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_queue() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let (commands_in, commands_out) = mpsc::channel(2 * MAX_QUEUED_COMMANDS);
+        let (responses_in, _responses_out) = std::sync::mpsc::channel();
+
+        // A stop sent behind more commands than the queue holds still goes first
+        for id in 0..MAX_QUEUED_COMMANDS + 5 {
+            commands_in
+                .send(request::get::stored_stats(id as u8))
+                .await
+                .unwrap();
+        }
+        commands_in.send(request::stop()).await.unwrap();
+        drop(commands_in);
+
+        run(
+            walkingpad,
+            ConnectOptions::default(),
+            commands_out,
+            responses_in,
+            Shared::default(),
+        )
+        .await;
+
+        assert_eq!(
+            peer.next_write().await,
+            Some(request::stop().as_bytes().to_vec())
+        );
+        let mut writes = 0;
+        while peer.next_write().await.is_some() {
+            writes += 1;
+        }
+        assert_eq!(writes, MAX_QUEUED_COMMANDS - 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_polling() {
+        let (transport, mut peer) = memory_transport();
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+        let (commands_in, commands_out) = mpsc::channel(10);
+        let (responses_in, _responses_out) = std::sync::mpsc::channel();
+        let shared = Shared::default();
+        let options = ConnectOptions {
+            polling: Some(PollingOptions::default()),
+            ..Default::default()
+        };
+
+        let session = run(
+            walkingpad,
+            options,
+            commands_out,
+            responses_in,
+            shared.clone(),
+        );
+        let pad = async {
+            let start = Instant::now();
+            let state = request::get::state().as_bytes().to_vec();
+            let Ok(Response::State(running)) = Response::parse(&STATE) else {
+                panic!("expected a state");
+            };
+            let idle = Response::State(State {
+                motor_state: MotorState::Stopped,
+                ..running
+            })
+            .encode();
+
+            assert_eq!(peer.next_write().await, Some(state.clone()));
+            peer.notify(uuid_from_u16(0xfe01), &idle);
+            assert_eq!(peer.next_write().await, Some(state.clone()));
+            assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+            peer.notify(uuid_from_u16(0xfe01), &STATE);
+            assert_eq!(peer.next_write().await, Some(state.clone()));
+            assert_eq!(start.elapsed(), Duration::from_secs(6));
+
+            // Commands don't wait for the next poll
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            commands_in.send(speed(30)).await.unwrap();
+            assert_eq!(peer.next_write().await, Some(speed(30).as_bytes().to_vec()));
+            assert_eq!(peer.next_write().await, Some(state));
+            assert_eq!(start.elapsed(), Duration::from_secs(7));
+
+            shared.is_polling_paused.store(true, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            assert_eq!(peer.try_next_write(), None);
+
+            drop(commands_in);
+            peer
+        };
+
+        let (_, mut peer) = futures::join!(session, pad);
+        assert_eq!(peer.next_write().await, None);
+    }
+}
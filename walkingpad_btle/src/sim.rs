@@ -0,0 +1,151 @@
+//! A transport to a simulated WalkingPad, to develop and test without the treadmill.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::BoxStream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use walkingpad_sim::Simulator;
+
+use crate::transport::{broadcast_stream, Frame, FrameStream, Transport};
+use crate::{ConnectionEvent, Error, Result};
+
+/// How often the simulation moves forward.
+const TICK: Duration = Duration::from_millis(100);
+
+/// A transport to a [`Simulator`] speaking the WalkingPad's own protocol, run on the tokio
+/// runtime it's created from.
+pub struct SimTransport {
+    simulator: Arc<Mutex<Simulator>>,
+    frames: broadcast::Sender<Frame>,
+    events: broadcast::Sender<ConnectionEvent>,
+    is_connected: AtomicBool,
+    clock: JoinHandle<()>,
+}
+
+impl SimTransport {
+    /// Must be called from a tokio runtime, whose time drives the simulation.
+    pub fn new(simulator: Simulator) -> SimTransport {
+        let simulator = Arc::new(Mutex::new(simulator));
+        let (frames, _) = broadcast::channel(64);
+        let (events, _) = broadcast::channel(16);
+
+        let clock = {
+            let simulator = simulator.clone();
+
+            tokio::spawn(async move {
+                let mut last_tick = Instant::now();
+                let mut ticks = tokio::time::interval(TICK);
+
+                loop {
+                    let now = ticks.tick().await;
+                    simulator.lock().unwrap().advance(now - last_tick);
+                    last_tick = now;
+                }
+            })
+        };
+
+        SimTransport {
+            simulator,
+            frames,
+            events,
+            is_connected: AtomicBool::new(true),
+            clock,
+        }
+    }
+
+    /// The simulated treadmill, e.g. to check what the client made of it.
+    pub fn simulator(&self) -> Arc<Mutex<Simulator>> {
+        self.simulator.clone()
+    }
+}
+
+impl Transport for SimTransport {
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        if !self.is_connected.load(Ordering::SeqCst) {
+            return future::ready(Err(Error::ConnectionClosed)).boxed();
+        }
+
+        // Like the WalkingPad, garbage is ignored
+        match self.simulator.lock().unwrap().handle(frame) {
+            Ok(Some(answer)) => {
+                let _ = self.frames.send(Frame {
+                    characteristic: uuid_from_u16(0xfe01),
+                    data: answer.to_vec(),
+                });
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!("The simulator ignored {:02x?}: {}", frame, err),
+        }
+
+        future::ready(Ok(())).boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        let frames = broadcast_stream(self.frames.subscribe());
+        future::ready(Ok(frames)).boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        broadcast_stream(self.events.subscribe())
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        future::ready(Ok(self.is_connected.load(Ordering::SeqCst))).boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        self.is_connected.store(false, Ordering::SeqCst);
+        self.clock.abort();
+        let _ = self.events.send(ConnectionEvent::Disconnected);
+        future::ready(Ok(())).boxed()
+    }
+}
+
+impl Drop for SimTransport {
+    fn drop(&mut self) {
+        self.clock.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Backend, WalkingPad};
+    use walkingpad_protocol::response::MotorState;
+    use walkingpad_protocol::{request, Speed};
+
+    #[tokio::test(start_paused = true)]
+    async fn test() {
+        let mut simulator = Simulator::new();
+        simulator.store_run(Duration::from_secs(600), 500, 800);
+
+        let walkingpad =
+            WalkingPad::with_transport(SimTransport::new(simulator), Backend::WalkingPad)
+                .await
+                .unwrap();
+
+        walkingpad.send(request::start()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        let speed = Speed::from_hm_per_hour(30);
+        walkingpad.set_speed_verified(speed).await.unwrap();
+        let state = walkingpad.get_state().await.unwrap();
+        assert_eq!(state.motor_state, MotorState::Running);
+        assert_eq!(state.speed, speed);
+
+        walkingpad.send(request::stop()).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        let latest = walkingpad.get_latest_stored_stats().await.unwrap();
+        assert_eq!(latest.next_id, Some(1));
+        let first = walkingpad.get_stored_stats(1).await.unwrap();
+        assert_eq!(first.distance, 500);
+        assert_eq!(first.next_id, None);
+    }
+}
@@ -0,0 +1,156 @@
+//! The links a [`WalkingPad`](crate::WalkingPad) client can run over.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use crate::{ConnectionEvent, Error, Result};
+
+pub type FrameStream = Pin<Box<dyn Stream<Item = Frame> + Send>>;
+
+/// A notification received from the treadmill.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// The characteristic the frame was notified on.
+    pub characteristic: Uuid,
+    pub data: Vec<u8>,
+}
+
+/// Carries the frames between the client and the treadmill.
+///
+/// The frames are already encoded for the treadmill's protocol, throttling and decoding are left
+/// to the client.
+pub trait Transport: Send + Sync {
+    /// Writes a frame to the treadmill's write characteristic.
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+
+    /// Returns a new stream of every frame received from now on.
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>>;
+
+    /// Returns a new stream of the changes of the link's state from now on.
+    fn events(&self) -> BoxStream<'static, ConnectionEvent>;
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>>;
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Creates a transport kept in memory, along with the peer playing the treadmill's part.
+pub fn memory_transport() -> (MemoryTransport, MemoryPeer) {
+    let (writes_in, writes_out) = mpsc::unbounded_channel();
+    let (frames, _) = broadcast::channel(64);
+    let (events, _) = broadcast::channel(16);
+    let is_connected = Arc::new(AtomicBool::new(true));
+
+    let transport = MemoryTransport {
+        writes: writes_in,
+        frames: frames.clone(),
+        events: events.clone(),
+        is_connected: is_connected.clone(),
+    };
+    let peer = MemoryPeer {
+        writes: writes_out,
+        frames,
+        events,
+        is_connected,
+    };
+
+    (transport, peer)
+}
+
+/// A transport to a [`MemoryPeer`], for tests.
+pub struct MemoryTransport {
+    writes: mpsc::UnboundedSender<Vec<u8>>,
+    frames: broadcast::Sender<Frame>,
+    events: broadcast::Sender<ConnectionEvent>,
+    is_connected: Arc<AtomicBool>,
+}
+
+/// The treadmill's end of a [`MemoryTransport`].
+pub struct MemoryPeer {
+    writes: mpsc::UnboundedReceiver<Vec<u8>>,
+    frames: broadcast::Sender<Frame>,
+    events: broadcast::Sender<ConnectionEvent>,
+    is_connected: Arc<AtomicBool>,
+}
+
+impl MemoryPeer {
+    /// Waits for the next frame written by the client.
+    /// Returns `None` once the transport is dropped.
+    pub async fn next_write(&mut self) -> Option<Vec<u8>> {
+        self.writes.recv().await
+    }
+
+    /// Returns the frame written by the client, if any, without waiting.
+    pub fn try_next_write(&mut self) -> Option<Vec<u8>> {
+        self.writes.try_recv().ok()
+    }
+
+    /// Delivers a frame to the client's frame streams.
+    pub fn notify(&self, characteristic: Uuid, data: &[u8]) {
+        let _ = self.frames.send(Frame {
+            characteristic,
+            data: data.to_vec(),
+        });
+    }
+
+    /// Simulates the loss of the link.
+    pub fn drop_link(&self) {
+        self.is_connected.store(false, Ordering::SeqCst);
+        let _ = self.events.send(ConnectionEvent::Disconnected);
+    }
+}
+
+/// Only keeps the items sent from now on, skipping those a slow receiver missed.
+pub(crate) fn broadcast_stream<T: Clone + Send + 'static>(
+    receiver: broadcast::Receiver<T>,
+) -> BoxStream<'static, T> {
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(item) => return Some((item, receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+impl Transport for MemoryTransport {
+    fn write<'a>(&'a self, frame: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        let result = if self.is_connected.load(Ordering::SeqCst) {
+            self.writes
+                .send(frame.to_vec())
+                .map_err(|_| Error::ConnectionClosed)
+        } else {
+            Err(Error::ConnectionClosed)
+        };
+
+        future::ready(result).boxed()
+    }
+
+    fn frames(&self) -> BoxFuture<'_, Result<FrameStream>> {
+        let frames = broadcast_stream(self.frames.subscribe());
+        future::ready(Ok(frames)).boxed()
+    }
+
+    fn events(&self) -> BoxStream<'static, ConnectionEvent> {
+        broadcast_stream(self.events.subscribe())
+    }
+
+    fn is_connected(&self) -> BoxFuture<'_, Result<bool>> {
+        future::ready(Ok(self.is_connected.load(Ordering::SeqCst))).boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+        self.is_connected.store(false, Ordering::SeqCst);
+        let _ = self.events.send(ConnectionEvent::Disconnected);
+        future::ready(Ok(())).boxed()
+    }
+}
@@ -0,0 +1,522 @@
+//! A protocol-agnostic interface over the treadmills this crate can drive.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{Settings, State, StoredStats};
+use walkingpad_protocol::{Mode, Request, Response, Speed, Units};
+
+use crate::session::Shared;
+use crate::{
+    clear_history, connect_impl, sync_history, Backend, ConnectOptions, ConnectionEvent, Error,
+    QueryOptions, Received, Result, SyncOptions, SyncProgress, SyncReport, WalkingPadReceiver,
+    WalkingPadSender, Watch,
+};
+
+mod sealed {
+    /// Gives the treadmills the connection their common operations are built on.
+    pub trait Sealed {
+        fn router(&self) -> &super::Router;
+    }
+}
+
+/// Common operations supported by every treadmill, regardless of the protocol it speaks.
+///
+/// The trait is sealed: it's implemented by the treadmills of this crate only, each protocol
+/// being added as a new one, see [`NativeTreadmill`] and [`FtmsTreadmill`].
+pub trait Treadmill: sealed::Sealed + Send + Sync {
+    /// Sends a raw WalkingPad request, the typed operations below being preferred.
+    /// Treadmills which can't carry out the request silently drop it.
+    ///
+    /// While reconnecting, the request is dropped unless the [`ReconnectPolicy`](crate::ReconnectPolicy) replays the
+    /// commands, a stop being kept either way.
+    fn send(&self, request: Request) -> Result<()> {
+        Ok(self.router().sender.send(request)?)
+    }
+
+    /// Subscribes to the state updates of the treadmill.
+    fn states(&self) -> Receiver<State> {
+        self.router().subscribe_states()
+    }
+
+    /// Subscribes to every response of the treadmill, each subscriber receiving its own copy.
+    fn responses(&self) -> Receiver<Response> {
+        self.router().subscribe_responses()
+    }
+
+    /// Like [`responses`](Treadmill::responses), with the malformed frames and the time each
+    /// item was received.
+    fn received(&self) -> Receiver<Received> {
+        self.router().subscribe_received()
+    }
+
+    /// The last state received, whoever asked for it.
+    fn latest_state(&self) -> Watch<State> {
+        self.router().latest_state.clone()
+    }
+
+    /// The last settings received, whoever asked for them.
+    fn latest_settings(&self) -> Watch<Settings> {
+        self.router().latest_settings.clone()
+    }
+
+    /// Subscribes to the link's losses and recoveries, see [`ReconnectPolicy`](crate::ReconnectPolicy).
+    fn connection_events(&self) -> Receiver<ConnectionEvent> {
+        self.router().subscribe_events()
+    }
+
+    /// Queries the settings stored on the treadmill.
+    fn settings(&self) -> Result<Settings>;
+
+    /// Retrieves the runs stored on the treadmill from the oldest to the latest, leaving them
+    /// there, see [`sync_history`].
+    fn history(&self) -> (Vec<StoredStats>, Option<Error>);
+
+    /// Retrieves the runs stored on the treadmill without clearing them, see [`sync_history`].
+    fn sync_history(
+        &self,
+        options: &SyncOptions,
+        progress: &mut dyn FnMut(SyncProgress),
+    ) -> SyncReport;
+
+    /// Clears the runs stored on the treadmill after a complete sync, see [`clear_history`].
+    fn clear_history(&self, report: &SyncReport) -> Result<()>;
+
+    fn start(&self) -> Result<()> {
+        self.send(request::start())
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.send(request::stop())
+    }
+
+    fn set_speed(&self, speed: Speed) -> Result<()> {
+        self.send(request::set::speed(speed))
+    }
+
+    fn set_mode(&self, mode: Mode) -> Result<()> {
+        self.send(request::set::mode(mode))
+    }
+
+    fn set_units(&self, units: Units) -> Result<()> {
+        self.send(request::set::units(units))
+    }
+
+    /// Asks the treadmill for a state update, which is delivered to the [`states`] subscribers.
+    ///
+    /// [`states`]: Treadmill::states
+    fn poll_state(&self) -> Result<()> {
+        self.send(request::get::state())
+    }
+}
+
+/// Connects to the first treadmill found, whichever protocol it speaks.
+pub fn connect_treadmill() -> Result<Box<dyn Treadmill>> {
+    connect_treadmill_with(ConnectOptions::default())
+}
+
+/// Connects to the treadmill described by the options, whichever protocol it speaks.
+pub fn connect_treadmill_with(options: ConnectOptions) -> Result<Box<dyn Treadmill>> {
+    let query_options = options.query;
+    let (sender, receiver, backend, shared) = connect_impl(options)?;
+    let router = Router::new(sender, receiver, shared, query_options);
+
+    Ok(match backend {
+        Backend::WalkingPad => Box::new(NativeTreadmill(router)),
+        Backend::Ftms => Box::new(FtmsTreadmill(router)),
+    })
+}
+
+/// A treadmill speaking the WalkingPad's own protocol.
+pub struct NativeTreadmill(Router);
+
+impl NativeTreadmill {
+    pub fn new(sender: WalkingPadSender, receiver: WalkingPadReceiver) -> NativeTreadmill {
+        NativeTreadmill(Router::new(
+            sender,
+            stamp(receiver),
+            Shared::default(),
+            QueryOptions::default(),
+        ))
+    }
+}
+
+impl sealed::Sealed for NativeTreadmill {
+    fn router(&self) -> &Router {
+        &self.0
+    }
+}
+
+impl Treadmill for NativeTreadmill {
+    fn settings(&self) -> Result<Settings> {
+        let settings = self
+            .0
+            .settings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // Discard settings nobody asked for, so that the answer is fresh
+        while settings.try_recv().is_ok() {}
+
+        for _ in 0..=self.0.query_options.retries {
+            self.send(request::get::settings())?;
+
+            match settings.recv_timeout(self.0.query_options.timeout) {
+                Ok(s) => return Ok(s),
+                Err(RecvTimeoutError::Timeout) => log::warn!("recv() timeout"),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::ConnectionClosed),
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    fn history(&self) -> (Vec<StoredStats>, Option<Error>) {
+        let options = SyncOptions {
+            timeout: self.0.query_options.timeout,
+            retries: self.0.query_options.retries,
+            ..SyncOptions::default()
+        };
+        let mut report = self.sync_history(&options, &mut |_| {});
+        report.records.reverse();
+
+        (report.records, report.error)
+    }
+
+    fn sync_history(
+        &self,
+        options: &SyncOptions,
+        progress: &mut dyn FnMut(SyncProgress),
+    ) -> SyncReport {
+        self.0.without_polling(|sender, stored_stats| {
+            sync_history(sender, stored_stats, options, progress)
+        })
+    }
+
+    fn clear_history(&self, report: &SyncReport) -> Result<()> {
+        clear_history(&self.0.sender, report)
+    }
+}
+
+/// A treadmill speaking the standard Fitness Machine Service protocol.
+///
+/// Those treadmills don't expose their settings nor their past runs.
+pub struct FtmsTreadmill(Router);
+
+impl FtmsTreadmill {
+    pub fn new(sender: WalkingPadSender, receiver: WalkingPadReceiver) -> FtmsTreadmill {
+        FtmsTreadmill(Router::new(
+            sender,
+            stamp(receiver),
+            Shared::default(),
+            QueryOptions::default(),
+        ))
+    }
+}
+
+impl sealed::Sealed for FtmsTreadmill {
+    fn router(&self) -> &Router {
+        &self.0
+    }
+}
+
+impl Treadmill for FtmsTreadmill {
+    fn settings(&self) -> Result<Settings> {
+        Err(Error::Unsupported)
+    }
+
+    fn history(&self) -> (Vec<StoredStats>, Option<Error>) {
+        (vec![], Some(Error::Unsupported))
+    }
+
+    fn sync_history(&self, _: &SyncOptions, _: &mut dyn FnMut(SyncProgress)) -> SyncReport {
+        SyncReport {
+            error: Some(Error::Unsupported),
+            ..SyncReport::default()
+        }
+    }
+
+    fn clear_history(&self, _: &SyncReport) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// FTMS treadmills are always driven by hand.
+    fn set_mode(&self, _: Mode) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// FTMS treadmills display the units they were set up with.
+    fn set_units(&self, _: Units) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// FTMS treadmills notify their state on their own.
+    fn poll_state(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Stamps the responses of a connection made by the application, as they are taken from its
+/// channel.
+fn stamp(receiver: WalkingPadReceiver) -> Receiver<Received> {
+    let (received_in, received_out) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for (sequence, response) in (0..).zip(receiver) {
+            if received_in
+                .send(Received::new(sequence, Ok(response)))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    received_out
+}
+
+// No lock of this crate is held over anything left half done by a panic, so a poisoned one is
+// used as is.
+type Subscribers<T> = Arc<Mutex<Vec<Sender<T>>>>;
+
+/// Sends a copy to each subscriber, forgetting those gone.
+fn broadcast<T: Clone>(subscribers: &Subscribers<T>, value: &T) {
+    subscribers
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|s| s.send(value.clone()).is_ok());
+}
+
+/// Dispatches the responses of a connection by kind, so that state updates don't get in the way
+/// of queries.
+pub struct Router {
+    sender: WalkingPadSender,
+    state_subscribers: Subscribers<State>,
+    response_subscribers: Subscribers<Response>,
+    received_subscribers: Subscribers<Received>,
+    latest_state: Watch<State>,
+    latest_settings: Watch<Settings>,
+    shared: Shared,
+    query_options: QueryOptions,
+    settings: Mutex<Receiver<Settings>>,
+    stored_stats: Mutex<WalkingPadReceiver>,
+}
+
+impl Router {
+    fn new(
+        sender: WalkingPadSender,
+        receiver: Receiver<Received>,
+        shared: Shared,
+        query_options: QueryOptions,
+    ) -> Router {
+        let state_subscribers = Subscribers::default();
+        let response_subscribers = Subscribers::default();
+        let received_subscribers = Subscribers::default();
+        let latest_state = Watch::new();
+        let latest_settings = Watch::new();
+        let (settings_in, settings_out) = mpsc::channel();
+        let (stored_stats_in, stored_stats_out) = mpsc::channel();
+
+        {
+            let state_subscribers = state_subscribers.clone();
+            let response_subscribers = response_subscribers.clone();
+            let received_subscribers = received_subscribers.clone();
+            let latest_state = latest_state.clone();
+            let latest_settings = latest_settings.clone();
+
+            std::thread::spawn(move || {
+                while let Ok(received) = receiver.recv() {
+                    broadcast(&received_subscribers, &received);
+
+                    let Some(response) = received.into_response() else {
+                        continue;
+                    };
+
+                    // Watched before it's broadcast, so that a subscriber reading it finds it
+                    match &response {
+                        Response::State(state) => latest_state.set(state.clone()),
+                        Response::Settings(settings) => latest_settings.set(settings.clone()),
+                        Response::StoredStats(_) => {}
+                    }
+                    broadcast(&response_subscribers, &response);
+
+                    match response {
+                        Response::State(state) => broadcast(&state_subscribers, &state),
+                        Response::Settings(settings) => {
+                            let _ = settings_in.send(settings);
+                        }
+                        Response::StoredStats(_) => {
+                            let _ = stored_stats_in.send(response);
+                        }
+                    }
+                }
+            });
+        }
+
+        Router {
+            sender,
+            state_subscribers,
+            response_subscribers,
+            received_subscribers,
+            latest_state,
+            latest_settings,
+            shared,
+            query_options,
+            settings: Mutex::new(settings_out),
+            stored_stats: Mutex::new(stored_stats_out),
+        }
+    }
+
+    /// Runs `f` over the stored stats answers, with the state polls held back since they would
+    /// delay every step.
+    fn without_polling<T>(&self, f: impl FnOnce(&WalkingPadSender, &WalkingPadReceiver) -> T) -> T {
+        // A panic in an earlier `f` left nothing half done in the channel
+        let stored_stats = self
+            .stored_stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let _pause = PollingPause::new(&self.shared.is_polling_paused);
+        f(&self.sender, &stored_stats)
+    }
+
+    fn subscribe_states(&self) -> Receiver<State> {
+        let (state_in, state_out) = mpsc::channel();
+        self.state_subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(state_in);
+        state_out
+    }
+
+    fn subscribe_responses(&self) -> Receiver<Response> {
+        let (response_in, response_out) = mpsc::channel();
+        self.response_subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(response_in);
+        response_out
+    }
+
+    fn subscribe_received(&self) -> Receiver<Received> {
+        let (received_in, received_out) = mpsc::channel();
+        self.received_subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(received_in);
+        received_out
+    }
+
+    fn subscribe_events(&self) -> Receiver<ConnectionEvent> {
+        let (event_in, event_out) = mpsc::channel();
+        self.shared
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event_in);
+        event_out
+    }
+}
+
+/// Holds the state polls back until dropped, even by a panic.
+struct PollingPause<'a>(&'a AtomicBool);
+
+impl PollingPause<'_> {
+    fn new(is_polling_paused: &AtomicBool) -> PollingPause<'_> {
+        is_polling_paused.store(true, Ordering::SeqCst);
+        PollingPause(is_polling_paused)
+    }
+}
+
+impl Drop for PollingPause<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use walkingpad_protocol::response::MotorState;
+    use walkingpad_protocol::Mode;
+
+    #[test]
+    fn test() {
+        let (sender, _commands) = mpsc::sync_channel(10);
+        let (responses_in, responses_out) = mpsc::channel();
+        let treadmill = NativeTreadmill::new(sender, responses_out);
+
+        let subscribers = [treadmill.responses(), treadmill.responses()];
+        let received = treadmill.received();
+        let latest_state = treadmill.latest_state();
+        assert_eq!(latest_state.get(), None);
+
+        let state = State {
+            motor_state: MotorState::Running,
+            speed: Speed::from_hm_per_hour(25),
+            mode: Mode::Manual,
+            run_time: Duration::ZERO,
+            distance: 0,
+            nb_steps: 0,
+            unknown: [0; 4],
+        };
+        responses_in.send(Response::State(state.clone())).unwrap();
+
+        for subscriber in subscribers {
+            let response = subscriber.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert!(matches!(response, Response::State(s) if s == state));
+        }
+        assert_eq!(latest_state.get(), Some(state));
+
+        let received = received.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(received.sequence, 0);
+        assert!(received.item.is_ok());
+        assert_eq!(treadmill.latest_settings().get(), None);
+
+        // Reading the history leaves it on the treadmill
+        let (sender, commands) = mpsc::sync_channel::<Request>(10);
+        let (responses_in, responses_out) = mpsc::channel();
+        let treadmill = NativeTreadmill::new(sender, responses_out);
+        let runs = [(20, Some(1)), (10, None)].map(|(start_time, next_id)| StoredStats {
+            current_time: 1000,
+            start_time,
+            duration: Duration::from_secs(60),
+            distance: 100,
+            nb_steps: 100,
+            next_id,
+        });
+
+        let pad = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for request in commands {
+                let id = request.as_bytes()[3];
+                let stats = runs[if id == 1 { 1 } else { 0 }].clone();
+                responses_in.send(Response::StoredStats(stats)).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let (history, err) = treadmill.history();
+        assert!(err.is_none());
+        assert_eq!(
+            history.iter().map(|s| s.start_time).collect::<Vec<_>>(),
+            [10, 20]
+        );
+
+        // The polls resume even if the history is given up on by a panic
+        let paused = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            treadmill.0.without_polling(|_, _| panic!("given up"))
+        }));
+        assert!(paused.is_err());
+        assert!(!treadmill.0.shared.is_polling_paused.load(Ordering::SeqCst));
+        assert!(treadmill.history().1.is_none());
+
+        drop(treadmill);
+        let requests = pad.join().unwrap();
+        assert!(!requests.contains(&request::clear_stats()));
+    }
+}
@@ -0,0 +1,230 @@
+//! Setters confirming the treadmill applied the change, since the WalkingPad doesn't acknowledge
+//! its writes and drops some of them.
+
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use tokio::time::Instant;
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{Settings, State};
+use walkingpad_protocol::{InfoFlags, Mode, Request, Sensitivity, Speed, Units};
+
+use crate::{Error, Result, WalkingPad};
+
+/// How hard to try before giving up with [`Error::NotApplied`].
+#[derive(Copy, Clone, Debug)]
+pub struct VerifyOptions {
+    /// How many times the request is written.
+    pub attempts: u32,
+    /// How long to watch for the change after each write, the belt takes a while to reach a new
+    /// speed.
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> VerifyOptions {
+        VerifyOptions {
+            attempts: 3,
+            timeout: Duration::from_secs(3),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl WalkingPad {
+    pub fn set_verify_options(&mut self, options: VerifyOptions) {
+        self.verify_options = options;
+    }
+
+    pub async fn set_speed_verified(&self, speed: Speed) -> Result<()> {
+        let request = request::set::speed(speed);
+        self.verify(request, read_state, |state| state.speed == speed)
+            .await
+    }
+
+    pub async fn set_mode_verified(&self, mode: Mode) -> Result<()> {
+        let request = request::set::mode(mode);
+        self.verify(request, read_state, |state| state.mode == mode)
+            .await
+    }
+
+    pub async fn set_max_speed_verified(&self, speed: Speed) -> Result<()> {
+        let request = request::set::max_speed(speed);
+        self.verify(request, read_settings, |settings| {
+            settings.max_speed == speed
+        })
+        .await
+    }
+
+    pub async fn set_start_speed_verified(&self, speed: Speed) -> Result<()> {
+        let request = request::set::start_speed(speed);
+        self.verify(request, read_settings, |settings| {
+            settings.start_speed == speed
+        })
+        .await
+    }
+
+    pub async fn set_sensitivity_verified(&self, sensitivity: Sensitivity) -> Result<()> {
+        let request = request::set::sensitivity(sensitivity);
+        self.verify(request, read_settings, |settings| {
+            settings.sensitivity == sensitivity
+        })
+        .await
+    }
+
+    pub async fn set_display_verified(&self, flags: InfoFlags) -> Result<()> {
+        let request = request::set::display(flags);
+        self.verify(request, read_settings, |settings| settings.display == flags)
+            .await
+    }
+
+    pub async fn set_units_verified(&self, units: Units) -> Result<()> {
+        let request = request::set::units(units);
+        self.verify(request, read_settings, |settings| settings.units == units)
+            .await
+    }
+
+    pub async fn set_locked_verified(&self, is_locked: bool) -> Result<()> {
+        let request = request::set::locked(is_locked);
+        self.verify(request, read_settings, |settings| {
+            settings.is_locked == is_locked
+        })
+        .await
+    }
+
+    /// Writes the request until `read` returns a value which it was `applied` to.
+    ///
+    /// Gives up with [`Error::Timeout`] rather than [`Error::NotApplied`] if `read` never got an
+    /// answer.
+    async fn verify<T>(
+        &self,
+        request: Request,
+        read: impl for<'a> Fn(&'a WalkingPad) -> BoxFuture<'a, Result<T>>,
+        applied: impl Fn(&T) -> bool,
+    ) -> Result<()> {
+        let options = self.verify_options;
+        let mut is_answered = false;
+
+        for attempt in 1..=options.attempts {
+            self.send(request.clone()).await?;
+            let deadline = Instant::now() + options.timeout;
+
+            loop {
+                // An unanswered read is just another miss, the treadmill drops some of them
+                match read(self).await {
+                    Ok(value) if applied(&value) => return Ok(()),
+                    Ok(_) => is_answered = true,
+                    Err(Error::Timeout) => log::warn!("No answer while verifying {:?}", request),
+                    Err(err) => return Err(err),
+                }
+
+                if Instant::now() + options.poll_interval > deadline {
+                    break;
+                }
+                tokio::time::sleep(options.poll_interval).await;
+            }
+
+            log::warn!("{:?} wasn't applied after attempt {}", request, attempt);
+        }
+
+        match is_answered {
+            true => Err(Error::NotApplied(request)),
+            false => Err(Error::Timeout),
+        }
+    }
+}
+
+fn read_state(walkingpad: &WalkingPad) -> BoxFuture<'_, Result<State>> {
+    walkingpad.get_state().boxed()
+}
+
+fn read_settings(walkingpad: &WalkingPad) -> BoxFuture<'_, Result<Settings>> {
+    walkingpad.get_settings().boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{memory_transport, Backend, MemoryPeer};
+    use btleplug::api::bleuuid::uuid_from_u16;
+    use walkingpad_protocol::fixtures::STATE;
+    use walkingpad_protocol::Response;
+
+    /// Plays a WalkingPad dropping the speed changes before the `applied_from`th one, and leaving
+    /// the first `silent_polls` polls unanswered.
+    async fn play_pad(mut peer: MemoryPeer, applied_from: u32, silent_polls: u32) -> u32 {
+        let Ok(Response::State(mut state)) = Response::parse(&STATE) else {
+            panic!("expected a state");
+        };
+        let mut speed_changes = 0;
+        let mut polls = 0;
+
+        while let Some(write) = peer.next_write().await {
+            if write == request::get::state().as_bytes() {
+                polls += 1;
+                if polls > silent_polls {
+                    peer.notify(
+                        uuid_from_u16(0xfe01),
+                        &Response::State(state.clone()).encode(),
+                    );
+                }
+            } else {
+                speed_changes += 1;
+                if speed_changes >= applied_from {
+                    state.speed = Speed::from_hm_per_hour(write[3]);
+                }
+            }
+        }
+
+        speed_changes
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test() {
+        let speed = Speed::from_hm_per_hour(30);
+
+        let (transport, peer) = memory_transport();
+        let pad = tokio::spawn(play_pad(peer, 2, 0));
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+
+        walkingpad.set_speed_verified(speed).await.unwrap();
+        drop(walkingpad);
+        assert_eq!(pad.await.unwrap(), 2);
+
+        let (transport, peer) = memory_transport();
+        let pad = tokio::spawn(play_pad(peer, u32::MAX, 0));
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+
+        let result = walkingpad.set_speed_verified(speed).await;
+        assert!(matches!(result, Err(Error::NotApplied(r)) if r == request::set::speed(speed)));
+        drop(walkingpad);
+        assert_eq!(pad.await.unwrap(), VerifyOptions::default().attempts);
+
+        // The first read times out, the request is written again
+        let (transport, peer) = memory_transport();
+        let pad = tokio::spawn(play_pad(peer, 1, 3));
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+
+        walkingpad.set_speed_verified(speed).await.unwrap();
+        drop(walkingpad);
+        assert_eq!(pad.await.unwrap(), 2);
+
+        let (transport, peer) = memory_transport();
+        let pad = tokio::spawn(play_pad(peer, 1, u32::MAX));
+        let walkingpad = WalkingPad::with_transport(transport, Backend::WalkingPad)
+            .await
+            .unwrap();
+
+        let result = walkingpad.set_speed_verified(speed).await;
+        assert!(matches!(result, Err(Error::Timeout)));
+        drop(walkingpad);
+        assert_eq!(pad.await.unwrap(), VerifyOptions::default().attempts);
+    }
+}
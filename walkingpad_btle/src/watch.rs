@@ -0,0 +1,103 @@
+//! The latest value of a kind of response, readable from any thread without consuming anything.
+
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+/// A handle on the latest value received, cheap to clone and share.
+pub struct Watch<T> {
+    inner: Arc<(Mutex<Slot<T>>, Condvar)>,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    version: u64,
+}
+
+impl<T: Clone> Watch<T> {
+    pub(crate) fn new() -> Watch<T> {
+        Watch {
+            inner: Arc::new((
+                Mutex::new(Slot {
+                    value: None,
+                    version: 0,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    pub(crate) fn set(&self, value: T) {
+        let (slot, updated) = &*self.inner;
+        let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        slot.value = Some(value);
+        slot.version += 1;
+        updated.notify_all();
+    }
+
+    /// Returns `None` until the first value is received.
+    pub fn get(&self) -> Option<T> {
+        self.inner
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .value
+            .clone()
+    }
+
+    /// Waits for the next value received, returning `None` if none comes in time.
+    pub fn wait_for_update(&self, timeout: Duration) -> Option<T> {
+        let (slot, updated) = &*self.inner;
+        let slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        let version = slot.version;
+
+        let (slot, _) = updated
+            .wait_timeout_while(slot, timeout, |s| s.version == version)
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match slot.version == version {
+            true => None,
+            false => slot.value.clone(),
+        }
+    }
+}
+
+impl<T> Clone for Watch<T> {
+    fn clone(&self) -> Watch<T> {
+        Watch {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let watch = Watch::new();
+        assert_eq!(watch.get(), None);
+        assert_eq!(watch.wait_for_update(Duration::from_millis(10)), None);
+
+        let reader = watch.clone();
+        let waiter = std::thread::spawn(move || reader.wait_for_update(Duration::from_secs(10)));
+        // The waiter may not be waiting yet, so keep updating until it's done
+        while !waiter.is_finished() {
+            watch.set(1);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(waiter.join().unwrap(), Some(1));
+        assert_eq!(watch.get(), Some(1));
+
+        // A reader panicking doesn't take the watch down with it
+        let reader = watch.clone();
+        let _ = std::thread::spawn(move || {
+            let _slot = reader.inner.0.lock().unwrap();
+            panic!("reader panicked");
+        })
+        .join();
+        watch.set(2);
+        assert_eq!(watch.get(), Some(2));
+    }
+}
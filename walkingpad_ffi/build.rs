@@ -0,0 +1,21 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Generates the header into `OUT_DIR`, the committed `include/walkingpad.h` being checked
+/// against it by the tests.
+fn main() -> Result<(), Box<dyn Error>> {
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))?;
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()?
+        .write_to_file(out_dir.join("walkingpad.h"));
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    Ok(())
+}
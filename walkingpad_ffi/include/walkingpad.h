@@ -0,0 +1,287 @@
+/* Generated by cbindgen from walkingpad_ffi, do not edit. */
+
+#ifndef WALKINGPAD_H
+#define WALKINGPAD_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Error codes mirroring the protocol's errors.
+ */
+typedef enum WalkingPadError {
+  WALKING_PAD_ERROR_OK = 0,
+  WALKING_PAD_ERROR_INVALID_SPEED,
+  WALKING_PAD_ERROR_INVALID_TYPE,
+  WALKING_PAD_ERROR_INVALID_RESPONSE_HEADER,
+  WALKING_PAD_ERROR_INVALID_RESPONSE_FOOTER,
+  WALKING_PAD_ERROR_BYTES_AFTER_FOOTER,
+  WALKING_PAD_ERROR_RESPONSE_TOO_SHORT,
+  WALKING_PAD_ERROR_MISSING_PARAMETER,
+  /**
+   * A pointer argument was null.
+   */
+  WALKING_PAD_ERROR_NULL_POINTER,
+  WALKING_PAD_ERROR_INVALID_REQUEST_HEADER,
+  WALKING_PAD_ERROR_INVALID_REQUEST_FOOTER,
+  WALKING_PAD_ERROR_INVALID_REQUEST_LENGTH,
+  WALKING_PAD_ERROR_INVALID_REQUEST_CHECKSUM,
+} WalkingPadError;
+
+typedef enum WalkingPadMotorState {
+  WALKING_PAD_MOTOR_STATE_STOPPED,
+  WALKING_PAD_MOTOR_STATE_RUNNING,
+  WALKING_PAD_MOTOR_STATE_STARTING,
+  WALKING_PAD_MOTOR_STATE_UNKNOWN,
+} WalkingPadMotorState;
+
+typedef enum WalkingPadMode {
+  WALKING_PAD_MODE_AUTO = 0,
+  WALKING_PAD_MODE_MANUAL = 1,
+  WALKING_PAD_MODE_SLEEP = 2,
+  WALKING_PAD_MODE_CALIBRATION = 4,
+} WalkingPadMode;
+
+typedef enum WalkingPadSensitivity {
+  WALKING_PAD_SENSITIVITY_HIGH = 1,
+  WALKING_PAD_SENSITIVITY_MEDIUM = 2,
+  WALKING_PAD_SENSITIVITY_LOW = 3,
+} WalkingPadSensitivity;
+
+typedef enum WalkingPadUnits {
+  WALKING_PAD_UNITS_METRIC = 0,
+  WALKING_PAD_UNITS_IMPERIAL = 1,
+} WalkingPadUnits;
+
+/**
+ * Opaque handle over a request.
+ */
+typedef struct WalkingPadRequest WalkingPadRequest;
+
+/**
+ * Plain-old-data counterpart of the protocol's `State`.
+ */
+typedef struct WalkingPadState {
+  enum WalkingPadMotorState motor_state;
+  uint8_t speed_hm_per_hour;
+  enum WalkingPadMode mode;
+  uint32_t run_time_secs;
+  /**
+   * In meters.
+   */
+  uint32_t distance;
+  uint32_t nb_steps;
+} WalkingPadState;
+
+/**
+ * Plain-old-data counterpart of the protocol's `Settings`.
+ */
+typedef struct WalkingPadSettings {
+  /**
+   * The significance of this field is unclear.
+   */
+  uint8_t goal_type;
+  /**
+   * The significance of this field is unclear.
+   */
+  uint32_t goal;
+  /**
+   * May tell whether the WalkingPad is in calibration mode.
+   */
+  uint8_t calibration;
+  uint8_t max_speed_hm_per_hour;
+  uint8_t start_speed_hm_per_hour;
+  enum WalkingPadMode start_mode;
+  enum WalkingPadSensitivity sensitivity;
+  /**
+   * Bit flags of the displayed statistics: time, speed, distance, calorie and step, from the
+   * least significant bit.
+   */
+  uint8_t display;
+  bool is_locked;
+  enum WalkingPadUnits units;
+} WalkingPadSettings;
+
+/**
+ * Plain-old-data counterpart of the protocol's `StoredStats`.
+ */
+typedef struct WalkingPadStoredStats {
+  uint32_t current_time;
+  uint32_t start_time;
+  uint32_t duration_secs;
+  /**
+   * In meters.
+   */
+  uint32_t distance;
+  uint32_t nb_steps;
+  /**
+   * 0 when this is the last record.
+   */
+  uint8_t next_id;
+} WalkingPadStoredStats;
+
+/**
+ * Tagged union of the responses, switch on `tag` before reading a field.
+ */
+enum WalkingPadResponse_Tag
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  WALKING_PAD_RESPONSE_STATE,
+  WALKING_PAD_RESPONSE_SETTINGS,
+  WALKING_PAD_RESPONSE_STORED_STATS,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum WalkingPadResponse_Tag WalkingPadResponse_Tag;
+#else
+typedef uint8_t WalkingPadResponse_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct WalkingPadResponse {
+  WalkingPadResponse_Tag tag;
+  union {
+    struct {
+      struct WalkingPadState state;
+    };
+    struct {
+      struct WalkingPadSettings settings;
+    };
+    struct {
+      struct WalkingPadStoredStats stored_stats;
+    };
+  };
+} WalkingPadResponse;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns a static, nul-terminated description of the error, `err` being one of the
+ * `WalkingPadError` values.
+ */
+const char *walkingpad_error_message(int err);
+
+/**
+ * Parses the `len` bytes of a notification into `out`.
+ *
+ * # Safety
+ *
+ * `bytes` must point to `len` readable bytes and `out` to a writable `WalkingPadResponse`.
+ */
+enum WalkingPadError walkingpad_response_parse(const uint8_t *bytes,
+                                               size_t len,
+                                               struct WalkingPadResponse *out);
+
+/**
+ * Returns the bytes to write to the WalkingPad, storing their count in `len`.
+ * The bytes live as long as the request.
+ *
+ * # Safety
+ *
+ * `request` must be a live handle and `len` must point to a writable `size_t`.
+ */
+const uint8_t *walkingpad_request_bytes(const struct WalkingPadRequest *request, size_t *len);
+
+/**
+ * Releases a request, null is ignored.
+ *
+ * # Safety
+ *
+ * `request` must be null or a handle which hasn't been released yet.
+ */
+void walkingpad_request_free(struct WalkingPadRequest *request);
+
+struct WalkingPadRequest *walkingpad_request_start(void);
+
+struct WalkingPadRequest *walkingpad_request_stop(void);
+
+/**
+ * Clears all data associated with past runs stored on the WalkingPad.
+ */
+struct WalkingPadRequest *walkingpad_request_clear_stats(void);
+
+struct WalkingPadRequest *walkingpad_request_get_state(void);
+
+struct WalkingPadRequest *walkingpad_request_get_settings(void);
+
+struct WalkingPadRequest *walkingpad_request_get_latest_stored_stats(void);
+
+struct WalkingPadRequest *walkingpad_request_get_stored_stats(uint8_t id);
+
+/**
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_speed(uint8_t hm_per_hour,
+                                                  struct WalkingPadRequest **out);
+
+/**
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_max_speed(uint8_t hm_per_hour,
+                                                      struct WalkingPadRequest **out);
+
+/**
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_start_speed(uint8_t hm_per_hour,
+                                                        struct WalkingPadRequest **out);
+
+/**
+ * `mode` is one of the `WalkingPadMode` values.
+ *
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_mode(uint8_t mode, struct WalkingPadRequest **out);
+
+struct WalkingPadRequest *walkingpad_request_set_calibration_mode(bool enabled);
+
+struct WalkingPadRequest *walkingpad_request_set_auto_start(bool enabled);
+
+/**
+ * `sensitivity` is one of the `WalkingPadSensitivity` values.
+ *
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_sensitivity(uint8_t sensitivity,
+                                                        struct WalkingPadRequest **out);
+
+/**
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_display(uint8_t flags, struct WalkingPadRequest **out);
+
+/**
+ * `units` is one of the `WalkingPadUnits` values.
+ *
+ * # Safety
+ *
+ * `out` must point to a writable request pointer.
+ */
+enum WalkingPadError walkingpad_request_set_units(uint8_t units, struct WalkingPadRequest **out);
+
+struct WalkingPadRequest *walkingpad_request_set_locked(bool is_locked);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WALKINGPAD_H */
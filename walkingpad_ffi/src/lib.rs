@@ -0,0 +1,538 @@
+/*!
+    C ABI for the WalkingPad protocol, see `include/walkingpad.h`.
+
+    Requests are exposed as opaque handles which must be released with
+    `walkingpad_request_free`, while responses are parsed into plain-old-data structs owned by
+    the caller. Fallible functions return a [`WalkingPadError`], `WALKING_PAD_ERROR_OK` on success.
+
+    ```c
+    WalkingPadRequest *request = NULL;
+    if (walkingpad_request_set_speed(25, &request) == WALKING_PAD_ERROR_OK) {
+        size_t len = 0;
+        const uint8_t *bytes = walkingpad_request_bytes(request, &len);
+        // write `bytes` to the 0xfe02 characteristic
+        walkingpad_request_free(request);
+    }
+
+    WalkingPadResponse response;
+    if (walkingpad_response_parse(notification, notification_len, &response) == WALKING_PAD_ERROR_OK
+        && response.tag == WALKING_PAD_RESPONSE_STATE) {
+        printf("%u m\n", response.state.distance);
+    }
+    ```
+*/
+
+use std::ffi::{c_char, c_int};
+
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{MotorState, Settings, State, StoredStats};
+use walkingpad_protocol::{Error, InfoFlags, Mode, Request, Response, Sensitivity, Speed, Units};
+
+/// Error codes mirroring the protocol's errors.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadError {
+    Ok = 0,
+    InvalidSpeed,
+    InvalidType,
+    InvalidResponseHeader,
+    InvalidResponseFooter,
+    BytesAfterFooter,
+    ResponseTooShort,
+    MissingParameter,
+    /// A pointer argument was null.
+    NullPointer,
+    InvalidRequestHeader,
+    InvalidRequestFooter,
+    InvalidRequestLength,
+    InvalidRequestChecksum,
+}
+
+impl From<Error> for WalkingPadError {
+    fn from(err: Error) -> WalkingPadError {
+        match err {
+            Error::InvalidSpeed(_) => WalkingPadError::InvalidSpeed,
+            Error::InvalidType(..) => WalkingPadError::InvalidType,
+            Error::InvalidResponseHeader(_) => WalkingPadError::InvalidResponseHeader,
+            Error::InvalidResponseFooter(_) => WalkingPadError::InvalidResponseFooter,
+            Error::BytesAfterFooter => WalkingPadError::BytesAfterFooter,
+            Error::ResponseTooShort => WalkingPadError::ResponseTooShort,
+            Error::MissingParameter => WalkingPadError::MissingParameter,
+            Error::InvalidRequestHeader(_) => WalkingPadError::InvalidRequestHeader,
+            Error::InvalidRequestFooter(_) => WalkingPadError::InvalidRequestFooter,
+            Error::InvalidRequestLength(_) => WalkingPadError::InvalidRequestLength,
+            Error::InvalidRequestChecksum(_) => WalkingPadError::InvalidRequestChecksum,
+        }
+    }
+}
+
+impl WalkingPadError {
+    const ALL: [WalkingPadError; 13] = [
+        WalkingPadError::Ok,
+        WalkingPadError::InvalidSpeed,
+        WalkingPadError::InvalidType,
+        WalkingPadError::InvalidResponseHeader,
+        WalkingPadError::InvalidResponseFooter,
+        WalkingPadError::BytesAfterFooter,
+        WalkingPadError::ResponseTooShort,
+        WalkingPadError::MissingParameter,
+        WalkingPadError::NullPointer,
+        WalkingPadError::InvalidRequestHeader,
+        WalkingPadError::InvalidRequestFooter,
+        WalkingPadError::InvalidRequestLength,
+        WalkingPadError::InvalidRequestChecksum,
+    ];
+
+    /// C callers may pass any integer, which can't be turned into the enum as is.
+    fn from_code(code: c_int) -> Option<WalkingPadError> {
+        Self::ALL.into_iter().find(|err| *err as c_int == code)
+    }
+}
+
+/// Returns a static, nul-terminated description of the error, `err` being one of the
+/// `WalkingPadError` values.
+#[no_mangle]
+pub extern "C" fn walkingpad_error_message(err: c_int) -> *const c_char {
+    let Some(err) = WalkingPadError::from_code(err) else {
+        return c"unknown error".as_ptr();
+    };
+
+    let message: &'static [u8] = match err {
+        WalkingPadError::Ok => b"no error\0",
+        WalkingPadError::InvalidSpeed => b"the speed is greater than 60 hm/h\0",
+        WalkingPadError::InvalidType => b"the value isn't valid for its type\0",
+        WalkingPadError::InvalidResponseHeader => b"the response header is invalid\0",
+        WalkingPadError::InvalidResponseFooter => b"the response footer is invalid\0",
+        WalkingPadError::BytesAfterFooter => b"the response continues past footer\0",
+        WalkingPadError::ResponseTooShort => b"the response is missing bytes\0",
+        WalkingPadError::MissingParameter => b"the message is missing a parameter\0",
+        WalkingPadError::NullPointer => b"a pointer argument is null\0",
+        WalkingPadError::InvalidRequestHeader => b"the request header is invalid\0",
+        WalkingPadError::InvalidRequestFooter => b"the request footer is invalid\0",
+        WalkingPadError::InvalidRequestLength => b"the request length is invalid\0",
+        WalkingPadError::InvalidRequestChecksum => b"the request checksum is invalid\0",
+    };
+    message.as_ptr().cast()
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadMode {
+    Auto = 0,
+    Manual = 1,
+    Sleep = 2,
+    Calibration = 4,
+}
+
+impl From<Mode> for WalkingPadMode {
+    fn from(mode: Mode) -> WalkingPadMode {
+        match mode {
+            Mode::Auto => WalkingPadMode::Auto,
+            Mode::Manual => WalkingPadMode::Manual,
+            Mode::Sleep => WalkingPadMode::Sleep,
+            Mode::Calibration => WalkingPadMode::Calibration,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadSensitivity {
+    High = 1,
+    Medium = 2,
+    Low = 3,
+}
+
+impl From<Sensitivity> for WalkingPadSensitivity {
+    fn from(sensitivity: Sensitivity) -> WalkingPadSensitivity {
+        match sensitivity {
+            Sensitivity::High => WalkingPadSensitivity::High,
+            Sensitivity::Medium => WalkingPadSensitivity::Medium,
+            Sensitivity::Low => WalkingPadSensitivity::Low,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadUnits {
+    Metric = 0,
+    Imperial = 1,
+}
+
+impl From<Units> for WalkingPadUnits {
+    fn from(units: Units) -> WalkingPadUnits {
+        match units {
+            Units::Metric => WalkingPadUnits::Metric,
+            Units::Imperial => WalkingPadUnits::Imperial,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadMotorState {
+    Stopped,
+    Running,
+    Starting,
+    Unknown,
+}
+
+impl From<MotorState> for WalkingPadMotorState {
+    fn from(motor_state: MotorState) -> WalkingPadMotorState {
+        match motor_state {
+            MotorState::Stopped => WalkingPadMotorState::Stopped,
+            MotorState::Running => WalkingPadMotorState::Running,
+            MotorState::Starting => WalkingPadMotorState::Starting,
+            MotorState::Unknown(_) => WalkingPadMotorState::Unknown,
+        }
+    }
+}
+
+/// Plain-old-data counterpart of the protocol's `State`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WalkingPadState {
+    pub motor_state: WalkingPadMotorState,
+    pub speed_hm_per_hour: u8,
+    pub mode: WalkingPadMode,
+    pub run_time_secs: u32,
+    /// In meters.
+    pub distance: u32,
+    pub nb_steps: u32,
+}
+
+impl From<State> for WalkingPadState {
+    fn from(state: State) -> WalkingPadState {
+        WalkingPadState {
+            motor_state: state.motor_state.into(),
+            speed_hm_per_hour: state.speed.hm_per_hour(),
+            mode: state.mode.into(),
+            // Counters are 3 bytes long, the seconds always fit
+            run_time_secs: state.run_time.as_secs() as u32,
+            distance: state.distance,
+            nb_steps: state.nb_steps,
+        }
+    }
+}
+
+/// Plain-old-data counterpart of the protocol's `Settings`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WalkingPadSettings {
+    /// The significance of this field is unclear.
+    pub goal_type: u8,
+    /// The significance of this field is unclear.
+    pub goal: u32,
+    /// May tell whether the WalkingPad is in calibration mode.
+    pub calibration: u8,
+    pub max_speed_hm_per_hour: u8,
+    pub start_speed_hm_per_hour: u8,
+    pub start_mode: WalkingPadMode,
+    pub sensitivity: WalkingPadSensitivity,
+    /// Bit flags of the displayed statistics: time, speed, distance, calorie and step, from the
+    /// least significant bit.
+    pub display: u8,
+    pub is_locked: bool,
+    pub units: WalkingPadUnits,
+}
+
+impl From<Settings> for WalkingPadSettings {
+    fn from(settings: Settings) -> WalkingPadSettings {
+        WalkingPadSettings {
+            goal_type: settings.goal_type,
+            goal: settings.goal,
+            calibration: settings.calibration,
+            max_speed_hm_per_hour: settings.max_speed.hm_per_hour(),
+            start_speed_hm_per_hour: settings.start_speed.hm_per_hour(),
+            start_mode: settings.start_mode.into(),
+            sensitivity: settings.sensitivity.into(),
+            display: settings.display.bits(),
+            is_locked: settings.is_locked,
+            units: settings.units.into(),
+        }
+    }
+}
+
+/// Plain-old-data counterpart of the protocol's `StoredStats`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WalkingPadStoredStats {
+    pub current_time: u32,
+    pub start_time: u32,
+    pub duration_secs: u32,
+    /// In meters.
+    pub distance: u32,
+    pub nb_steps: u32,
+    /// 0 when this is the last record.
+    pub next_id: u8,
+}
+
+impl From<StoredStats> for WalkingPadStoredStats {
+    fn from(stats: StoredStats) -> WalkingPadStoredStats {
+        WalkingPadStoredStats {
+            current_time: stats.current_time,
+            start_time: stats.start_time,
+            duration_secs: stats.duration.as_secs() as u32,
+            distance: stats.distance,
+            nb_steps: stats.nb_steps,
+            next_id: stats.next_id.unwrap_or(0),
+        }
+    }
+}
+
+/// Tagged union of the responses, switch on `tag` before reading a field.
+#[repr(C, u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WalkingPadResponse {
+    State(WalkingPadState),
+    Settings(WalkingPadSettings),
+    StoredStats(WalkingPadStoredStats),
+}
+
+impl From<Response> for WalkingPadResponse {
+    fn from(response: Response) -> WalkingPadResponse {
+        match response {
+            Response::State(inner) => WalkingPadResponse::State(inner.into()),
+            Response::Settings(inner) => WalkingPadResponse::Settings(inner.into()),
+            Response::StoredStats(inner) => WalkingPadResponse::StoredStats(inner.into()),
+        }
+    }
+}
+
+/// Parses the `len` bytes of a notification into `out`.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes and `out` to a writable `WalkingPadResponse`.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_response_parse(
+    bytes: *const u8,
+    len: usize,
+    out: *mut WalkingPadResponse,
+) -> WalkingPadError {
+    if bytes.is_null() || out.is_null() {
+        return WalkingPadError::NullPointer;
+    }
+
+    let bytes = std::slice::from_raw_parts(bytes, len);
+    match Response::parse(bytes) {
+        Ok(response) => {
+            out.write(response.into());
+            WalkingPadError::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Opaque handle over a request.
+pub struct WalkingPadRequest(Request);
+
+fn into_handle(request: Request) -> *mut WalkingPadRequest {
+    Box::into_raw(Box::new(WalkingPadRequest(request)))
+}
+
+/// Builds a request taking a speed, writing it to `out` when the speed is valid.
+unsafe fn speed_request(
+    hm_per_hour: u8,
+    build: fn(Speed) -> Request,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    if out.is_null() {
+        return WalkingPadError::NullPointer;
+    }
+
+    match Speed::try_from_hm_per_hour(hm_per_hour) {
+        Ok(speed) => {
+            out.write(into_handle(build(speed)));
+            WalkingPadError::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Builds a request taking a value of the protocol's types, writing it to `out` when the value is
+/// valid for its type.
+unsafe fn typed_request<T: TryFrom<u8, Error = Error>>(
+    value: u8,
+    build: fn(T) -> Request,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    if out.is_null() {
+        return WalkingPadError::NullPointer;
+    }
+
+    match T::try_from(value) {
+        Ok(value) => {
+            out.write(into_handle(build(value)));
+            WalkingPadError::Ok
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// Returns the bytes to write to the WalkingPad, storing their count in `len`.
+/// The bytes live as long as the request.
+///
+/// # Safety
+///
+/// `request` must be a live handle and `len` must point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_bytes(
+    request: *const WalkingPadRequest,
+    len: *mut usize,
+) -> *const u8 {
+    if request.is_null() || len.is_null() {
+        return std::ptr::null();
+    }
+
+    let bytes = (*request).0.as_bytes();
+    len.write(bytes.len());
+    bytes.as_ptr()
+}
+
+/// Releases a request, null is ignored.
+///
+/// # Safety
+///
+/// `request` must be null or a handle which hasn't been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_free(request: *mut WalkingPadRequest) {
+    if !request.is_null() {
+        drop(Box::from_raw(request));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_start() -> *mut WalkingPadRequest {
+    into_handle(request::start())
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_stop() -> *mut WalkingPadRequest {
+    into_handle(request::stop())
+}
+
+/// Clears all data associated with past runs stored on the WalkingPad.
+#[no_mangle]
+pub extern "C" fn walkingpad_request_clear_stats() -> *mut WalkingPadRequest {
+    into_handle(request::clear_stats())
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_get_state() -> *mut WalkingPadRequest {
+    into_handle(request::get::state())
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_get_settings() -> *mut WalkingPadRequest {
+    into_handle(request::get::settings())
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_get_latest_stored_stats() -> *mut WalkingPadRequest {
+    into_handle(request::get::latest_stored_stats())
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_get_stored_stats(id: u8) -> *mut WalkingPadRequest {
+    into_handle(request::get::stored_stats(id))
+}
+
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_speed(
+    hm_per_hour: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    speed_request(hm_per_hour, request::set::speed, out)
+}
+
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_max_speed(
+    hm_per_hour: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    speed_request(hm_per_hour, request::set::max_speed, out)
+}
+
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_start_speed(
+    hm_per_hour: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    speed_request(hm_per_hour, request::set::start_speed, out)
+}
+
+/// `mode` is one of the `WalkingPadMode` values.
+///
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_mode(
+    mode: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    typed_request(mode, request::set::mode, out)
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_set_calibration_mode(enabled: bool) -> *mut WalkingPadRequest {
+    into_handle(request::set::calibration_mode(enabled))
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_set_auto_start(enabled: bool) -> *mut WalkingPadRequest {
+    into_handle(request::set::auto_start(enabled))
+}
+
+/// `sensitivity` is one of the `WalkingPadSensitivity` values.
+///
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_sensitivity(
+    sensitivity: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    typed_request(sensitivity, request::set::sensitivity, out)
+}
+
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_display(
+    flags: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    typed_request::<InfoFlags>(flags, request::set::display, out)
+}
+
+/// `units` is one of the `WalkingPadUnits` values.
+///
+/// # Safety
+///
+/// `out` must point to a writable request pointer.
+#[no_mangle]
+pub unsafe extern "C" fn walkingpad_request_set_units(
+    units: u8,
+    out: *mut *mut WalkingPadRequest,
+) -> WalkingPadError {
+    typed_request(units, request::set::units, out)
+}
+
+#[no_mangle]
+pub extern "C" fn walkingpad_request_set_locked(is_locked: bool) -> *mut WalkingPadRequest {
+    into_handle(request::set::locked(is_locked))
+}
@@ -0,0 +1,90 @@
+#include <stdio.h>
+#include <string.h>
+
+#include "walkingpad.h"
+
+#define CHECK(cond)                                                            \
+  do {                                                                         \
+    if (!(cond)) {                                                             \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+      return 1;                                                                \
+    }                                                                          \
+  } while (0)
+
+int main(void) {
+  size_t len = 0;
+
+  WalkingPadRequest *start = walkingpad_request_start();
+  const uint8_t *bytes = walkingpad_request_bytes(start, &len);
+  const uint8_t expected_start[] = {0xf7, 0xa2, 0x04, 0x01, 0xa7, 0xfd};
+  CHECK(len == sizeof(expected_start));
+  CHECK(memcmp(bytes, expected_start, len) == 0);
+  walkingpad_request_free(start);
+
+  WalkingPadRequest *speed = NULL;
+  CHECK(walkingpad_request_set_speed(25, &speed) == WALKING_PAD_ERROR_OK);
+  bytes = walkingpad_request_bytes(speed, &len);
+  CHECK(len == 6 && bytes[3] == 25);
+  walkingpad_request_free(speed);
+
+  speed = NULL;
+  CHECK(walkingpad_request_set_speed(61, &speed) == WALKING_PAD_ERROR_INVALID_SPEED);
+  CHECK(speed == NULL);
+  CHECK(strlen(walkingpad_error_message(WALKING_PAD_ERROR_INVALID_SPEED)) > 0);
+  CHECK(strcmp(walkingpad_error_message(1000), "unknown error") == 0);
+
+  WalkingPadRequest *mode = NULL;
+  CHECK(walkingpad_request_set_mode(WALKING_PAD_MODE_MANUAL, &mode) == WALKING_PAD_ERROR_OK);
+  bytes = walkingpad_request_bytes(mode, &len);
+  CHECK(len == 6 && bytes[3] == 1);
+  walkingpad_request_free(mode);
+
+  mode = NULL;
+  CHECK(walkingpad_request_set_mode(3, &mode) == WALKING_PAD_ERROR_INVALID_TYPE);
+  CHECK(mode == NULL);
+  CHECK(walkingpad_request_set_units(2, &mode) == WALKING_PAD_ERROR_INVALID_TYPE);
+  CHECK(walkingpad_request_set_sensitivity(0, &mode) == WALKING_PAD_ERROR_INVALID_TYPE);
+  CHECK(walkingpad_request_set_units(WALKING_PAD_UNITS_IMPERIAL, NULL) ==
+        WALKING_PAD_ERROR_NULL_POINTER);
+
+  WalkingPadRequest *settings = walkingpad_request_get_settings();
+  bytes = walkingpad_request_bytes(settings, &len);
+  CHECK(len == 9);
+  walkingpad_request_free(settings);
+
+  const uint8_t state[] = {0xf8, 0xa2, 1, 25, 1, 0, 1, 44, 0, 0,
+                           50,   0,    2, 0,  0, 0, 0, 0, 0x1e, 0xfd};
+  WalkingPadResponse response;
+  CHECK(walkingpad_response_parse(state, sizeof(state), &response) == WALKING_PAD_ERROR_OK);
+  CHECK(response.tag == WALKING_PAD_RESPONSE_STATE);
+  CHECK(response.state.motor_state == WALKING_PAD_MOTOR_STATE_RUNNING);
+  CHECK(response.state.speed_hm_per_hour == 25);
+  CHECK(response.state.mode == WALKING_PAD_MODE_MANUAL);
+  CHECK(response.state.run_time_secs == 300);
+  CHECK(response.state.distance == 500);
+  CHECK(response.state.nb_steps == 512);
+
+  const uint8_t settings_frame[] = {0xf8, 0xa6, 1, 0, 0x0e, 0x10, 1, 60, 20, 1,
+                                    2,    0b11, 0, 0, 0,    0,    0, 0,  0x1c, 0xfd};
+  CHECK(walkingpad_response_parse(settings_frame, sizeof(settings_frame), &response) ==
+        WALKING_PAD_ERROR_OK);
+  CHECK(response.tag == WALKING_PAD_RESPONSE_SETTINGS);
+  CHECK(response.settings.goal_type == 1);
+  CHECK(response.settings.goal == 3600);
+  CHECK(response.settings.calibration == 1);
+  CHECK(response.settings.max_speed_hm_per_hour == 60);
+  CHECK(response.settings.start_mode == WALKING_PAD_MODE_MANUAL);
+
+  const uint8_t stored_stats[] = {0xf8, 0xa7, 0, 1, 0, 0, 0, 200, 0, 7,
+                                  8,    0,    0, 100, 0, 4, 0, 3, 0xea, 0xfd};
+  CHECK(walkingpad_response_parse(stored_stats, sizeof(stored_stats), &response) ==
+        WALKING_PAD_ERROR_OK);
+  CHECK(response.tag == WALKING_PAD_RESPONSE_STORED_STATS);
+  CHECK(response.stored_stats.duration_secs == 7 * 256 + 8);
+  CHECK(response.stored_stats.next_id == 3);
+
+  CHECK(walkingpad_response_parse(state, 10, &response) == WALKING_PAD_ERROR_RESPONSE_TOO_SHORT);
+  CHECK(walkingpad_response_parse(NULL, 0, &response) == WALKING_PAD_ERROR_NULL_POINTER);
+
+  return 0;
+}
@@ -0,0 +1,47 @@
+//! Compiles `tests/c/test.c` against the committed header and the shared library, then runs it.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // The test binary lives in `target/<profile>/deps`, next to which Cargo puts the library
+    let test_exe = std::env::current_exe().unwrap();
+    let lib_dir = test_exe.parent().unwrap().parent().unwrap();
+    let program = lib_dir.join("walkingpad_ffi_c_test");
+
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg("-std=c11")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/c/test.c"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lwalkingpad_ffi")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("unable to run the C compiler");
+    assert!(status.success(), "compiling the C test program failed");
+
+    let status = Command::new(&program).status().unwrap();
+    assert!(status.success(), "the C test program failed");
+}
+
+#[test]
+fn test_header_is_fresh() {
+    let generated = PathBuf::from(env!("OUT_DIR")).join("walkingpad.h");
+    let committed = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("include/walkingpad.h");
+
+    assert!(
+        std::fs::read_to_string(&generated).unwrap()
+            == std::fs::read_to_string(&committed).unwrap(),
+        "include/walkingpad.h is stale, copy {} over it",
+        generated.display()
+    );
+}
@@ -0,0 +1,27 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use walkingpad_protocol::fixtures::STATE;
+use walkingpad_protocol::view::ResponseView;
+use walkingpad_protocol::Response;
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("Response::parse", |b| {
+        b.iter(|| match Response::parse(black_box(&STATE)) {
+            Ok(Response::State(state)) => Some((state.motor_state, state.speed)),
+            _ => None,
+        })
+    });
+
+    c.bench_function("ResponseView::parse", |b| {
+        b.iter(|| match ResponseView::parse(black_box(&STATE)) {
+            Ok(ResponseView::State(state)) => Some((state.motor_state(), state.speed().ok()?)),
+            _ => None,
+        })
+    });
+
+    c.bench_function("ResponseView::to_response", |b| {
+        b.iter(|| ResponseView::parse(black_box(&STATE)).and_then(|view| view.to_response()))
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
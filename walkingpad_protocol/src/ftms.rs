@@ -0,0 +1,547 @@
+/*!
+    Conversions between the WalkingPad protocol and the Bluetooth Fitness Machine Service (FTMS).
+
+    Apps such as Zwift or Kinomap, as well as most sports watches, only understand the standard
+    FTMS characteristics. This module encodes a [`State`] into the Treadmill Data and Training
+    Status characteristics, and maps the operations written to the Fitness Machine Control Point
+    back into WalkingPad [`Request`]s.
+
+    The same conversions work in the other direction for treadmills which only expose FTMS:
+    [`update_state`] decodes their Treadmill Data into a [`State`], and
+    [`ControlPoint::from_request`] turns WalkingPad requests into Control Point operations.
+
+    # Examples
+
+    ```rust
+    use walkingpad_protocol::ftms::{self, ControlPoint};
+
+    let op = ControlPoint::parse(&[0x02, 0xf4, 0x01]).unwrap();
+    assert_eq!(op, ControlPoint::SetTargetSpeed(500));
+
+    if let Some(request) = op.to_request() {
+        // Write the request to the WalkingPad, then acknowledge the operation.
+        let indication = op.indication(ftms::ResultCode::Success);
+    }
+    ```
+*/
+
+use bitflags::bitflags;
+use strum_macros::FromRepr;
+
+use core::time::Duration;
+
+use super::request;
+use super::response::{MotorState, State};
+use super::{Error, Mode, Request, Result, Speed, Subject};
+
+/// UUID of the Fitness Machine service.
+pub const SERVICE_UUID: u16 = 0x1826;
+
+/// UUID of the Treadmill Data characteristic, which notifies [`TreadmillData`] payloads.
+pub const TREADMILL_DATA_UUID: u16 = 0x2acd;
+
+/// UUID of the Training Status characteristic, which notifies [`TrainingStatus`] payloads.
+pub const TRAINING_STATUS_UUID: u16 = 0x2ad3;
+
+/// UUID of the Fitness Machine Control Point characteristic, which accepts [`ControlPoint`]
+/// operations.
+pub const CONTROL_POINT_UUID: u16 = 0x2ad9;
+
+bitflags! {
+    /// Defines which fields are present in a Treadmill Data payload.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TreadmillDataFlags: u16 {
+        /// Unlike every other flag, this one being unset means the instantaneous speed is
+        /// present.
+        const MORE_DATA = 1 << 0;
+        const AVERAGE_SPEED = 1 << 1;
+        const TOTAL_DISTANCE = 1 << 2;
+        const INCLINATION = 1 << 3;
+        const ELEVATION_GAIN = 1 << 4;
+        const INSTANTANEOUS_PACE = 1 << 5;
+        const AVERAGE_PACE = 1 << 6;
+        const EXPENDED_ENERGY = 1 << 7;
+        const HEART_RATE = 1 << 8;
+        const METABOLIC_EQUIVALENT = 1 << 9;
+        const ELAPSED_TIME = 1 << 10;
+        const REMAINING_TIME = 1 << 11;
+        const FORCE_AND_POWER = 1 << 12;
+    }
+}
+
+bitflags! {
+    /// The kinds of machine an FTMS device advertises being, the service covering bikes and
+    /// rowers as well.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MachineTypes: u16 {
+        const TREADMILL = 1 << 0;
+        const CROSS_TRAINER = 1 << 1;
+        const STEP_CLIMBER = 1 << 2;
+        const STAIR_CLIMBER = 1 << 3;
+        const ROWER = 1 << 4;
+        const INDOOR_BIKE = 1 << 5;
+    }
+}
+
+impl MachineTypes {
+    /// Parses the data advertised along the Fitness Machine service: a flags byte, then the
+    /// machine types.
+    pub fn from_service_data(data: &[u8]) -> Option<MachineTypes> {
+        match data {
+            [_flags, low, high, ..] => Some(MachineTypes::from_bits_retain(u16::from_le_bytes([
+                *low, *high,
+            ]))),
+            _ => None,
+        }
+    }
+}
+
+const TREADMILL_DATA_SIZE: usize = 9;
+
+/// Encoded Treadmill Data payload carrying the speed, total distance and elapsed time.
+///
+/// FTMS has no step counter for treadmills, so [`State::nb_steps`] isn't transmitted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreadmillData([u8; TREADMILL_DATA_SIZE]);
+
+impl TreadmillData {
+    const FLAGS: TreadmillDataFlags =
+        TreadmillDataFlags::TOTAL_DISTANCE.union(TreadmillDataFlags::ELAPSED_TIME);
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&State> for TreadmillData {
+    fn from(state: &State) -> TreadmillData {
+        let flags = TreadmillData::FLAGS.bits().to_le_bytes();
+        let speed = speed_to_ftms(state.speed).to_le_bytes();
+        // The total distance is a 24-bit field
+        let distance = state.distance.min(0xff_ffff).to_le_bytes();
+        let elapsed = u16::try_from(state.run_time.as_secs())
+            .unwrap_or(u16::MAX)
+            .to_le_bytes();
+
+        TreadmillData([
+            flags[0],
+            flags[1],
+            speed[0],
+            speed[1],
+            distance[0],
+            distance[1],
+            distance[2],
+            elapsed[0],
+            elapsed[1],
+        ])
+    }
+}
+
+/// Updates `state` with the fields present in a Treadmill Data payload.
+///
+/// Treadmills may split their data over several notifications, so fields absent from the
+/// payload keep their previous value. Fields without a WalkingPad counterpart are skipped.
+pub fn update_state(state: &mut State, treadmill_data: &[u8]) -> Result<()> {
+    use TreadmillDataFlags as Flags;
+
+    let mut reader = treadmill_data.iter().copied();
+    let flags = Flags::from_bits_retain(read_u16(&mut reader)?);
+
+    if !flags.contains(Flags::MORE_DATA) {
+        state.speed = speed_from_ftms(read_u16(&mut reader)?);
+        state.motor_state = if state.speed.hm_per_hour() > 0 {
+            MotorState::Running
+        } else {
+            MotorState::Stopped
+        };
+    }
+
+    skip_if(&mut reader, flags.contains(Flags::AVERAGE_SPEED), 2)?;
+
+    if flags.contains(Flags::TOTAL_DISTANCE) {
+        state.distance = read_u24(&mut reader)?;
+    }
+
+    skip_if(&mut reader, flags.contains(Flags::INCLINATION), 4)?;
+    skip_if(&mut reader, flags.contains(Flags::ELEVATION_GAIN), 4)?;
+    skip_if(&mut reader, flags.contains(Flags::INSTANTANEOUS_PACE), 1)?;
+    skip_if(&mut reader, flags.contains(Flags::AVERAGE_PACE), 1)?;
+    skip_if(&mut reader, flags.contains(Flags::EXPENDED_ENERGY), 5)?;
+    skip_if(&mut reader, flags.contains(Flags::HEART_RATE), 1)?;
+    skip_if(&mut reader, flags.contains(Flags::METABOLIC_EQUIVALENT), 1)?;
+
+    if flags.contains(Flags::ELAPSED_TIME) {
+        state.run_time = Duration::from_secs(read_u16(&mut reader)?.into());
+    }
+
+    skip_if(&mut reader, flags.contains(Flags::REMAINING_TIME), 2)?;
+    skip_if(&mut reader, flags.contains(Flags::FORCE_AND_POWER), 4)?;
+
+    Ok(())
+}
+
+fn read_u8(reader: &mut impl Iterator<Item = u8>) -> Result<u8> {
+    reader.next().ok_or(Error::ResponseTooShort)
+}
+
+fn read_u16(reader: &mut impl Iterator<Item = u8>) -> Result<u16> {
+    Ok(u16::from_le_bytes([read_u8(reader)?, read_u8(reader)?]))
+}
+
+fn read_u24(reader: &mut impl Iterator<Item = u8>) -> Result<u32> {
+    Ok(u32::from_le_bytes([
+        read_u8(reader)?,
+        read_u8(reader)?,
+        read_u8(reader)?,
+        0,
+    ]))
+}
+
+fn skip_if(reader: &mut impl Iterator<Item = u8>, present: bool, size: usize) -> Result<()> {
+    if present {
+        for _ in 0..size {
+            read_u8(reader)?;
+        }
+    }
+    Ok(())
+}
+
+/// Defines the values of the Training Status characteristic relevant to the WalkingPad.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromRepr)]
+pub enum TrainingStatus {
+    Other = 0x00,
+    Idle = 0x01,
+    ManualMode = 0x0d,
+    PreWorkout = 0x0e,
+    PostWorkout = 0x0f,
+}
+
+impl TrainingStatus {
+    /// Encodes the status without the optional training status string.
+    pub const fn to_bytes(self) -> [u8; 2] {
+        const FLAGS: u8 = 0;
+        [FLAGS, self as u8]
+    }
+}
+
+impl From<&State> for TrainingStatus {
+    fn from(state: &State) -> TrainingStatus {
+        if state.mode == Mode::Sleep {
+            return TrainingStatus::Idle;
+        }
+
+        match state.motor_state {
+            MotorState::Stopped => TrainingStatus::Idle,
+            MotorState::Starting => TrainingStatus::PreWorkout,
+            MotorState::Running => TrainingStatus::ManualMode,
+            MotorState::Unknown(_) => TrainingStatus::Other,
+        }
+    }
+}
+
+/// Defines the Fitness Machine Control Point operations the WalkingPad can act upon.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ControlPoint {
+    /// Must be written by a client before any other operation.
+    RequestControl,
+    Reset,
+    /// Target speed in hundredths of a kilometer per hour.
+    SetTargetSpeed(u16),
+    StartOrResume,
+    Stop,
+    Pause,
+}
+
+impl ControlPoint {
+    const REQUEST_CONTROL: u8 = 0x00;
+    const RESET: u8 = 0x01;
+    const SET_TARGET_SPEED: u8 = 0x02;
+    const START_OR_RESUME: u8 = 0x07;
+    const STOP_OR_PAUSE: u8 = 0x08;
+    const RESPONSE_CODE: u8 = 0x80;
+
+    pub fn parse(bytes: &[u8]) -> Result<ControlPoint> {
+        let (&op_code, params) = bytes.split_first().ok_or(Error::MissingParameter)?;
+
+        match op_code {
+            Self::REQUEST_CONTROL => Ok(ControlPoint::RequestControl),
+            Self::RESET => Ok(ControlPoint::Reset),
+            Self::SET_TARGET_SPEED => match params {
+                [low, high, ..] => Ok(ControlPoint::SetTargetSpeed(u16::from_le_bytes([
+                    *low, *high,
+                ]))),
+                _ => Err(Error::MissingParameter),
+            },
+            Self::START_OR_RESUME => Ok(ControlPoint::StartOrResume),
+            Self::STOP_OR_PAUSE => match params.first() {
+                Some(1) => Ok(ControlPoint::Stop),
+                Some(2) => Ok(ControlPoint::Pause),
+                Some(&other) => Err(Error::InvalidType(other, "stop or pause")),
+                None => Err(Error::MissingParameter),
+            },
+            other => Err(Error::InvalidType(other, "FTMS op code")),
+        }
+    }
+
+    /// Returns the operation carrying out a WalkingPad request, if there is one.
+    ///
+    /// Only starting, stopping and setting the speed have an FTMS equivalent.
+    pub fn from_request(request: &Request) -> Option<ControlPoint> {
+        if *request == request::start() {
+            Some(ControlPoint::StartOrResume)
+        } else if *request == request::stop() {
+            Some(ControlPoint::Stop)
+        } else if request.subject() == Subject::State && request.request_type() == 1 {
+            let speed = Speed::from_hm_per_hour(request.param() as u8);
+            Some(ControlPoint::SetTargetSpeed(speed_to_ftms(speed)))
+        } else {
+            None
+        }
+    }
+
+    /// Writes the operation into `buf`, returning the bytes to send to the Control Point.
+    pub fn encode<'a>(&self, buf: &'a mut [u8; 3]) -> &'a [u8] {
+        buf[0] = self.op_code();
+        let len = match self {
+            ControlPoint::SetTargetSpeed(speed) => {
+                buf[1..3].copy_from_slice(&speed.to_le_bytes());
+                3
+            }
+            ControlPoint::Stop => {
+                buf[1] = 1;
+                2
+            }
+            ControlPoint::Pause => {
+                buf[1] = 2;
+                2
+            }
+            _ => 1,
+        };
+        &buf[..len]
+    }
+
+    pub const fn op_code(&self) -> u8 {
+        match self {
+            ControlPoint::RequestControl => Self::REQUEST_CONTROL,
+            ControlPoint::Reset => Self::RESET,
+            ControlPoint::SetTargetSpeed(_) => Self::SET_TARGET_SPEED,
+            ControlPoint::StartOrResume => Self::START_OR_RESUME,
+            ControlPoint::Stop | ControlPoint::Pause => Self::STOP_OR_PAUSE,
+        }
+    }
+
+    /// Returns the WalkingPad request carrying out this operation, if there is one.
+    ///
+    /// The WalkingPad has no notion of pausing, so pausing stops the belt.
+    pub fn to_request(&self) -> Option<Request> {
+        match self {
+            ControlPoint::RequestControl | ControlPoint::Reset => None,
+            ControlPoint::SetTargetSpeed(speed) => {
+                Some(request::set::speed(speed_from_ftms(*speed)))
+            }
+            ControlPoint::StartOrResume => Some(request::start()),
+            ControlPoint::Stop | ControlPoint::Pause => Some(request::stop()),
+        }
+    }
+
+    /// Encodes the indication acknowledging this operation.
+    pub const fn indication(&self, result: ResultCode) -> [u8; 3] {
+        [Self::RESPONSE_CODE, self.op_code(), result as u8]
+    }
+}
+
+/// Defines the result codes sent back in Control Point indications.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromRepr)]
+pub enum ResultCode {
+    Success = 0x01,
+    OpCodeNotSupported = 0x02,
+    InvalidParameter = 0x03,
+    OperationFailed = 0x04,
+    ControlNotPermitted = 0x05,
+}
+
+/// FTMS speeds are in hundredths of a kilometer per hour, ten times finer than the WalkingPad's.
+const fn speed_to_ftms(speed: Speed) -> u16 {
+    speed.hm_per_hour() as u16 * 10
+}
+
+const fn speed_from_ftms(speed: u16) -> Speed {
+    let hm_per_hour = speed.saturating_add(5) / 10;
+    if hm_per_hour > u8::MAX as u16 {
+        Speed::from_hm_per_hour(u8::MAX)
+    } else {
+        Speed::from_hm_per_hour(hm_per_hour as u8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(motor_state: MotorState) -> State {
+        State {
+            motor_state,
+            speed: Speed::from_hm_per_hour(35),
+            mode: Mode::Manual,
+            run_time: Duration::from_secs(600),
+            distance: 1230,
+            nb_steps: 1500,
+            unknown: [0; 4],
+        }
+    }
+
+    #[test]
+    fn test_treadmill_data() {
+        let data = TreadmillData::from(&state(MotorState::Running));
+
+        // Flags 0x0404, 3.50 km/h, 1230 m, 600 s, all little-endian
+        assert_eq!(
+            data.as_bytes(),
+            &[0x04, 0x04, 0x5e, 0x01, 0xce, 0x04, 0x00, 0x58, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_update_state() {
+        let mut decoded = state(MotorState::Stopped);
+        decoded.nb_steps = 0;
+        update_state(
+            &mut decoded,
+            TreadmillData::from(&state(MotorState::Running)).as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            decoded,
+            State {
+                nb_steps: 0,
+                ..state(MotorState::Running)
+            }
+        );
+
+        // Speed, average speed, distance, expended energy, heart rate and elapsed time
+        let mut decoded = state(MotorState::Running);
+        update_state(
+            &mut decoded,
+            &[
+                0x86, 0x05, 0x00, 0x00, 0x2c, 0x01, 0x0a, 0x00, 0x00, 0x14, 0x00, 0xff, 0xff, 0xff,
+                0x5a, 0x3c, 0x00,
+            ],
+        )
+        .unwrap();
+        assert_eq!(decoded.motor_state, MotorState::Stopped);
+        assert_eq!(decoded.speed, Speed::from_hm_per_hour(0));
+        assert_eq!(decoded.distance, 10);
+        assert_eq!(decoded.run_time, Duration::from_secs(60));
+
+        // Continuation packet without the instantaneous speed
+        update_state(&mut decoded, &[0x05, 0x00, 0xe8, 0x03, 0x00]).unwrap();
+        assert_eq!(decoded.speed, Speed::from_hm_per_hour(0));
+        assert_eq!(decoded.distance, 1000);
+
+        // The fastest speed on the wire clamps instead of overflowing
+        update_state(&mut decoded, &[0x00, 0x00, 0xff, 0xff]).unwrap();
+        assert_eq!(decoded.speed, Speed::from_hm_per_hour(60));
+
+        assert!(matches!(
+            update_state(&mut decoded, &[0x04, 0x04, 0x5e]),
+            Err(Error::ResponseTooShort)
+        ));
+    }
+
+    #[test]
+    fn test_machine_types() {
+        assert_eq!(
+            MachineTypes::from_service_data(&[0x01, 0x01, 0x00]),
+            Some(MachineTypes::TREADMILL)
+        );
+        assert_eq!(
+            MachineTypes::from_service_data(&[0x01, 0x20, 0x00]),
+            Some(MachineTypes::INDOOR_BIKE)
+        );
+        assert_eq!(MachineTypes::from_service_data(&[0x01]), None);
+    }
+
+    #[test]
+    fn test_from_request() {
+        let mut buf = [0; 3];
+
+        let op = ControlPoint::from_request(&request::start()).unwrap();
+        assert_eq!(op.encode(&mut buf), &[0x07]);
+
+        let op = ControlPoint::from_request(&request::stop()).unwrap();
+        assert_eq!(op.encode(&mut buf), &[0x08, 0x01]);
+
+        let op =
+            ControlPoint::from_request(&request::set::speed(Speed::from_hm_per_hour(35))).unwrap();
+        assert_eq!(op.encode(&mut buf), &[0x02, 0x5e, 0x01]);
+
+        assert_eq!(ControlPoint::from_request(&request::get::state()), None);
+        assert_eq!(ControlPoint::from_request(&request::get::settings()), None);
+    }
+
+    #[test]
+    fn test_training_status() {
+        assert_eq!(
+            TrainingStatus::from(&state(MotorState::Running)).to_bytes(),
+            [0x00, 0x0d]
+        );
+        assert_eq!(
+            TrainingStatus::from(&state(MotorState::Stopped)).to_bytes(),
+            [0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_control_point() {
+        assert_eq!(
+            ControlPoint::parse(&[0x00]).unwrap(),
+            ControlPoint::RequestControl
+        );
+        assert_eq!(
+            ControlPoint::parse(&[0x07]).unwrap().to_request(),
+            Some(request::start())
+        );
+        assert_eq!(
+            ControlPoint::parse(&[0x08, 0x01]).unwrap().to_request(),
+            Some(request::stop())
+        );
+        assert_eq!(
+            ControlPoint::parse(&[0x08, 0x02]).unwrap(),
+            ControlPoint::Pause
+        );
+
+        let set_speed = ControlPoint::parse(&[0x02, 0xf4, 0x01]).unwrap();
+        assert_eq!(set_speed, ControlPoint::SetTargetSpeed(500));
+        assert_eq!(
+            set_speed.to_request(),
+            Some(request::set::speed(Speed::from_hm_per_hour(50)))
+        );
+        assert_eq!(
+            set_speed.indication(ResultCode::Success),
+            [0x80, 0x02, 0x01]
+        );
+        assert_eq!(
+            ControlPoint::parse(&[0x02, 0xff, 0xff])
+                .unwrap()
+                .to_request(),
+            Some(request::set::speed(Speed::from_hm_per_hour(60)))
+        );
+
+        // Faster than the WalkingPad supports
+        assert_eq!(
+            ControlPoint::SetTargetSpeed(1800).to_request(),
+            Some(request::set::speed(Speed::from_hm_per_hour(60)))
+        );
+
+        assert!(matches!(
+            ControlPoint::parse(&[0x02, 0xf4]),
+            Err(Error::MissingParameter)
+        ));
+        assert!(matches!(
+            ControlPoint::parse(&[0x11]),
+            Err(Error::InvalidType(0x11, _))
+        ));
+    }
+}
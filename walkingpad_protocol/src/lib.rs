@@ -7,8 +7,12 @@
 
 #![no_std]
 
+#[doc(hidden)]
+pub mod fixtures;
+pub mod ftms;
 pub mod request;
 pub mod response;
+pub mod view;
 
 pub use request::Request;
 pub use response::Response;
@@ -22,6 +26,7 @@ use bitflags::bitflags;
 use strum_macros::FromRepr;
 
 const MESSAGE_FOOTER: u8 = 0xfd;
+const RESPONSE_HEADER: u8 = 0xf8;
 
 type Result<T> = core::result::Result<T, Error>;
 
@@ -33,6 +38,11 @@ pub enum Error {
     InvalidResponseFooter(u8),
     BytesAfterFooter,
     ResponseTooShort,
+    MissingParameter,
+    InvalidRequestHeader(u8),
+    InvalidRequestFooter(u8),
+    InvalidRequestLength(usize),
+    InvalidRequestChecksum(u8),
 }
 
 impl fmt::Display for Error {
@@ -49,10 +59,17 @@ impl fmt::Display for Error {
             InvalidResponseFooter(byte) => write!(f, "{} isn't a valid response footer", byte),
             BytesAfterFooter => write!(f, "the response continues past footer"),
             ResponseTooShort => write!(f, "the response is missing bytes"),
+            MissingParameter => write!(f, "the message is missing a parameter"),
+            InvalidRequestHeader(byte) => write!(f, "{} isn't a valid request header", byte),
+            InvalidRequestFooter(byte) => write!(f, "{} isn't a valid request footer", byte),
+            InvalidRequestLength(len) => write!(f, "{} bytes isn't a valid request length", len),
+            InvalidRequestChecksum(crc) => write!(f, "{} isn't the request's checksum", crc),
         }
     }
 }
 
+impl core::error::Error for Error {}
+
 /// Represents the speed values used in requests and responses.
 /// The WalkingPad displays speeds in kilometers per hour, but stores them internally in
 /// hectometers (100 meters) per hour to represent fractional values.
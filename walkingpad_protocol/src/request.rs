@@ -16,7 +16,7 @@ use either::Either;
 use core::fmt::Debug;
 use core::mem::size_of;
 
-use super::{InfoFlags, Mode, Sensitivity, Speed, Subject, Units, MESSAGE_FOOTER};
+use super::{Error, InfoFlags, Mode, Result, Sensitivity, Speed, Subject, Units, MESSAGE_FOOTER};
 
 /// Clears all data associated with past runs stored on the WalkingPad.
 pub fn clear_stats() -> Request {
@@ -99,6 +99,52 @@ pub mod set {
     }
 }
 
+/// Defines what a request asks of the WalkingPad, as the device understands it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    GetState,
+    GetSettings,
+    GetLatestStoredStats,
+    GetStoredStats(u8),
+    ClearStats,
+    Start,
+    Stop,
+    SetSpeed(Speed),
+    SetMode(Mode),
+    SetCalibrationMode(bool),
+    SetMaxSpeed(Speed),
+    SetStartSpeed(Speed),
+    SetAutoStart(bool),
+    SetSensitivity(Sensitivity),
+    SetDisplay(InfoFlags),
+    SetUnits(Units),
+    SetLocked(bool),
+}
+
+impl From<Command> for Request {
+    fn from(command: Command) -> Request {
+        match command {
+            Command::GetState => get::state(),
+            Command::GetSettings => get::settings(),
+            Command::GetLatestStoredStats => get::latest_stored_stats(),
+            Command::GetStoredStats(id) => get::stored_stats(id),
+            Command::ClearStats => clear_stats(),
+            Command::Start => start(),
+            Command::Stop => stop(),
+            Command::SetSpeed(speed) => set::speed(speed),
+            Command::SetMode(mode) => set::mode(mode),
+            Command::SetCalibrationMode(enabled) => set::calibration_mode(enabled),
+            Command::SetMaxSpeed(speed) => set::max_speed(speed),
+            Command::SetStartSpeed(speed) => set::start_speed(speed),
+            Command::SetAutoStart(enabled) => set::auto_start(enabled),
+            Command::SetSensitivity(sensitivity) => set::sensitivity(sensitivity),
+            Command::SetDisplay(flags) => set::display(flags),
+            Command::SetUnits(units) => set::units(units),
+            Command::SetLocked(is_locked) => set::locked(is_locked),
+        }
+    }
+}
+
 const U8_PARAM_SIZE: usize = core::mem::size_of::<u8>();
 const U32_PARAM_SIZE: usize = core::mem::size_of::<u32>();
 
@@ -119,32 +165,128 @@ impl Request {
         Request(Either::Right(RawRequest::new(request_type, subject, param)))
     }
 
+    /// Parses the bytes written by a client, the parameter's size being told by the length.
+    pub fn parse(bytes: &[u8]) -> Result<Request> {
+        let header = *bytes.first().ok_or(Error::InvalidRequestLength(0))?;
+        if header != REQUEST_HEADER {
+            return Err(Error::InvalidRequestHeader(header));
+        }
+
+        let footer = bytes[bytes.len() - 1];
+        if footer != MESSAGE_FOOTER {
+            return Err(Error::InvalidRequestFooter(footer));
+        }
+
+        let request = match bytes {
+            [_, subject, request_type, param, _crc, _] => {
+                Request::from_u8(*request_type, Subject::try_from(*subject)?, *param)
+            }
+            [_, subject, request_type, a, b, c, d, _crc, _] => Request::from_u32(
+                *request_type,
+                Subject::try_from(*subject)?,
+                u32::from_be_bytes([*a, *b, *c, *d]),
+            ),
+            _ => return Err(Error::InvalidRequestLength(bytes.len())),
+        };
+
+        // Right before the footer
+        let crc = bytes[bytes.len() - 2];
+        if crc != request.as_bytes()[bytes.len() - 2] {
+            return Err(Error::InvalidRequestChecksum(crc));
+        }
+
+        Ok(request)
+    }
+
+    /// Interprets the request the way the WalkingPad does.
+    pub fn command(&self) -> Result<Command> {
+        const GET: u8 = 0;
+        const STORED_STATS: u8 = 0xaa;
+        const LATEST_STATS: u32 = 255;
+
+        let param = self.param();
+        let flag = param != 0;
+        // Out of range either way
+        let byte = u8::try_from(param).unwrap_or(u8::MAX);
+        let speed = || Speed::try_from_hm_per_hour(byte);
+
+        let command = match (self.subject(), self.request_type()) {
+            (Subject::State, GET) => Command::GetState,
+            (Subject::State, 1) => Command::SetSpeed(speed()?),
+            (Subject::State, 2) => Command::SetMode(byte.try_into()?),
+            (Subject::State, 4) if flag => Command::Start,
+            (Subject::State, 4) => Command::Stop,
+            (Subject::Settings, GET) => Command::GetSettings,
+            (Subject::Settings, 2) => Command::SetCalibrationMode(flag),
+            (Subject::Settings, 3) => Command::SetMaxSpeed(speed()?),
+            (Subject::Settings, 4) => Command::SetStartSpeed(speed()?),
+            (Subject::Settings, 5) => Command::SetAutoStart(flag),
+            (Subject::Settings, 6) => Command::SetSensitivity(byte.try_into()?),
+            (Subject::Settings, 7) => Command::SetDisplay(byte.try_into()?),
+            (Subject::Settings, 8) => Command::SetUnits(byte.try_into()?),
+            (Subject::Settings, 9) => Command::SetLocked(flag),
+            (Subject::StoredStats, STORED_STATS) => match param {
+                0 => Command::ClearStats,
+                LATEST_STATS => Command::GetLatestStoredStats,
+                id => Command::GetStoredStats(id as u8),
+            },
+            (_, request_type) => return Err(Error::InvalidType(request_type, "request")),
+        };
+
+        Ok(command)
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         self.0
             .as_ref()
             .either(RawRequest::as_bytes, RawRequest::as_bytes)
     }
+
+    /// Whether writing this request makes writing the `earlier` one redundant, because both
+    /// change the same value or both ask for the same thing.
+    ///
+    /// Only a stop supersedes a stop, which must go through whatever comes after it.
+    pub fn supersedes(&self, earlier: &Request) -> bool {
+        const GET: u8 = 0;
+        const STORED_STATS: u8 = 0xaa;
+
+        if *earlier == stop() {
+            return *self == stop();
+        }
+
+        let is_setter = !matches!(self.request_type(), GET | STORED_STATS);
+
+        self.subject() == earlier.subject()
+            && self.request_type() == earlier.request_type()
+            && (is_setter || self == earlier)
+    }
+
+    pub(crate) fn subject(&self) -> Subject {
+        let subject = self.0.as_ref().either(|r| r.subject, |r| r.subject);
+        // The constructors all take a Subject variant, fine to unwrap
+        Subject::try_from(subject).unwrap()
+    }
+
+    pub(crate) fn request_type(&self) -> u8 {
+        self.0
+            .as_ref()
+            .either(|r| r.request_type, |r| r.request_type)
+    }
+
+    pub(crate) fn param(&self) -> u32 {
+        match &self.0 {
+            Either::Left(req_u8) => req_u8.param[0] as u32,
+            Either::Right(req_u32) => u32::from_be_bytes(req_u32.param),
+        }
+    }
 }
 
 impl Debug for Request {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let param = match &self.0 {
-            Either::Left(req_u8) => req_u8.param[0] as u32,
-            Either::Right(req_u32) => u32::from_be_bytes(req_u32.param),
-        };
-        // The constructors all take a Subject variant, fine to unwrap
-        let subject = match &self.0 {
-            Either::Left(req_u8) => Subject::try_from(req_u8.subject).unwrap(),
-            Either::Right(req_u32) => Subject::try_from(req_u32.subject).unwrap(),
-        };
-        let request_type = self
-            .0
-            .as_ref()
-            .either(|r| r.request_type, |r| r.request_type);
         f.debug_struct("Request")
-            .field("subject", &subject)
-            .field("request_type", &request_type)
-            .field("param", &param)
+            .field("subject", &self.subject())
+            .field("request_type", &self.request_type())
+            .field("param", &self.param())
             .finish()
     }
 }
@@ -229,5 +371,60 @@ mod test {
                 MESSAGE_FOOTER
             ]
         );
+
+        let speed = |hm_per_hour| set::speed(Speed::from_hm_per_hour(hm_per_hour));
+        assert!(speed(30).supersedes(&speed(20)));
+        assert!(stop().supersedes(&start()));
+        assert!(stop().supersedes(&stop()));
+        assert!(!start().supersedes(&stop()));
+        assert!(get::state().supersedes(&get::state()));
+        assert!(!speed(30).supersedes(&set::mode(Mode::Manual)));
+        assert!(!get::stored_stats(2).supersedes(&get::stored_stats(1)));
+        assert!(!set::max_speed(Speed::from_hm_per_hour(30)).supersedes(&speed(30)));
+
+        for command in [
+            Command::GetState,
+            Command::GetSettings,
+            Command::GetLatestStoredStats,
+            Command::GetStoredStats(3),
+            Command::ClearStats,
+            Command::Start,
+            Command::Stop,
+            Command::SetSpeed(Speed::from_hm_per_hour(30)),
+            Command::SetMode(Mode::Sleep),
+            Command::SetMaxSpeed(Speed::from_hm_per_hour(60)),
+            Command::SetSensitivity(Sensitivity::Low),
+            Command::SetDisplay(InfoFlags::TIME | InfoFlags::STEP),
+            Command::SetUnits(Units::Imperial),
+            Command::SetLocked(true),
+        ] {
+            let request = Request::from(command);
+            let parsed = Request::parse(request.as_bytes()).unwrap();
+            assert_eq!(parsed, request);
+            assert_eq!(parsed.command().unwrap(), command);
+        }
+
+        assert!(matches!(
+            Request::parse(&[0xf8, 0xa2, 0, 0, 0xa2, 0xfd]),
+            Err(Error::InvalidRequestHeader(0xf8))
+        ));
+        assert!(matches!(
+            Request::parse(&[0xf7, 0xa2, 0, 0, 0, 0xa2, 0xfd]),
+            Err(Error::InvalidRequestLength(7))
+        ));
+        assert!(matches!(
+            Request::parse(&[]),
+            Err(Error::InvalidRequestLength(0))
+        ));
+        assert!(matches!(
+            Request::parse(&[0xf7, 0xa2, 4, 1, 0xa8, 0xfd]),
+            Err(Error::InvalidRequestChecksum(0xa8))
+        ));
+        assert!(matches!(
+            Request::parse(&[0xf7, 0xa2, 1, 61, 0xe0, 0xfd])
+                .unwrap()
+                .command(),
+            Err(Error::InvalidSpeed(61))
+        ));
     }
 }
@@ -1,7 +1,10 @@
 use core::fmt::{Debug, Display, Formatter};
 use core::time::Duration;
 
-use super::{Error, InfoFlags, Mode, Result, Sensitivity, Speed, Subject, Units, MESSAGE_FOOTER};
+use super::{
+    Error, InfoFlags, Mode, Result, Sensitivity, Speed, Subject, Units, MESSAGE_FOOTER,
+    RESPONSE_HEADER,
+};
 
 /// Defines the state the WalkingPad's motor can be in.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
@@ -26,6 +29,19 @@ impl From<u8> for MotorState {
     }
 }
 
+impl From<MotorState> for u8 {
+    fn from(state: MotorState) -> u8 {
+        use MotorState::*;
+
+        match state {
+            Stopped => 0b0000,
+            Running => 0b0001,
+            Starting => 0b1001,
+            Unknown(value) => value,
+        }
+    }
+}
+
 /// Reprents the current state of the WalkingPad.
 #[derive(Clone, Debug, Eq, PartialEq, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -74,6 +90,18 @@ impl State {
     }
 }
 
+impl State {
+    fn encode(&self, writer: &mut Writer) {
+        writer.u8(self.motor_state.into());
+        writer.u8(self.speed.hm_per_hour());
+        writer.u8(self.mode as u8);
+        writer.u32(self.run_time.as_secs() as u32);
+        writer.u32(meter_to_decameter(self.distance));
+        writer.u32(self.nb_steps);
+        self.unknown.iter().for_each(|&b| writer.u8(b));
+    }
+}
+
 impl Display for State {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "State {{ ")?;
@@ -153,6 +181,22 @@ impl Settings {
     }
 }
 
+impl Settings {
+    fn encode(&self, writer: &mut Writer) {
+        writer.u8(self.goal_type);
+        writer.u32(self.goal);
+        writer.u8(self.calibration);
+        writer.u8(self.max_speed.hm_per_hour());
+        writer.u8(self.start_speed.hm_per_hour());
+        writer.u8(self.start_mode as u8);
+        writer.u8(self.sensitivity as u8);
+        writer.u8(self.display.bits());
+        writer.u8(self.is_locked as u8);
+        writer.u8(self.units as u8);
+        self.unknown.iter().for_each(|&b| writer.u8(b));
+    }
+}
+
 impl Display for Settings {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Settings {{ ")?;
@@ -209,6 +253,17 @@ impl StoredStats {
     }
 }
 
+impl StoredStats {
+    fn encode(&self, writer: &mut Writer) {
+        writer.u32(self.current_time);
+        writer.u32(self.start_time);
+        writer.u32(self.duration.as_secs() as u32);
+        writer.u32(meter_to_decameter(self.distance));
+        writer.u32(self.nb_steps);
+        writer.u8(self.next_id.unwrap_or(0));
+    }
+}
+
 impl Display for StoredStats {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "StoredStats {{ ")?;
@@ -289,11 +344,42 @@ impl Response {
         }
     }
 
+    /// Encodes the response the way the WalkingPad sends it.
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut writer = Writer {
+            buf: [0; ENCODED_LEN],
+            len: 0,
+        };
+
+        writer.u8(RESPONSE_HEADER);
+        match self {
+            Response::State(state) => {
+                writer.u8(Subject::State as u8);
+                state.encode(&mut writer);
+            }
+            Response::Settings(settings) => {
+                writer.u8(Subject::Settings as u8);
+                settings.encode(&mut writer);
+            }
+            Response::StoredStats(stats) => {
+                writer.u8(Subject::StoredStats as u8);
+                stats.encode(&mut writer);
+            }
+        }
+
+        // Like the requests', the checksum sums everything between the header and itself
+        let crc = writer.buf[1..writer.len]
+            .iter()
+            .fold(0u8, |crc, &b| crc.wrapping_add(b));
+        writer.u8(crc);
+        writer.u8(MESSAGE_FOOTER);
+
+        writer.buf
+    }
+
     fn parse_header(reader: &mut impl Iterator<Item = u8>) -> Result<()> {
         let byte = read_u8(reader)?;
 
-        const RESPONSE_HEADER: u8 = 0xf8;
-
         (byte == RESPONSE_HEADER)
             .then_some(())
             .ok_or(Error::InvalidResponseHeader(byte))
@@ -308,6 +394,28 @@ impl Response {
     }
 }
 
+/// The length of every response, whatever its subject.
+pub const ENCODED_LEN: usize = 20;
+
+struct Writer {
+    buf: [u8; ENCODED_LEN],
+    len: usize,
+}
+
+impl Writer {
+    fn u8(&mut self, byte: u8) {
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Counters are 3 bytes long, see [`read_u32`].
+    fn u32(&mut self, n: u32) {
+        for &b in &n.to_be_bytes()[1..] {
+            self.u8(b);
+        }
+    }
+}
+
 fn read_u32(reader: &mut impl Iterator<Item = u8>) -> Result<u32> {
     // Because the Wakling Pad uses 3-bytes long integer counters in respones
     Ok(u32::from_be_bytes([
@@ -322,6 +430,32 @@ fn read_u8(reader: &mut impl Iterator<Item = u8>) -> Result<u8> {
     reader.next().ok_or(Error::ResponseTooShort)
 }
 
-fn decameter_to_meter(n: u32) -> u32 {
+pub(crate) fn decameter_to_meter(n: u32) -> u32 {
     n * 10
 }
+
+fn meter_to_decameter(n: u32) -> u32 {
+    n / 10
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let state = [
+            0xf8, 0xa2, 1, 25, 1, 0, 1, 44, 0, 0, 50, 0, 2, 0, 0, 0, 0, 0, 0x1e, 0xfd,
+        ];
+        let settings = [
+            0xf8, 0xa6, 0, 0, 0, 0, 0, 60, 20, 1, 2, 0b11, 0, 0, 0, 0, 0, 0, 0xfc, 0xfd,
+        ];
+        let stored_stats = [
+            0xf8, 0xa7, 0, 1, 0, 0, 0, 200, 0, 0, 60, 0, 0, 10, 0, 0, 100, 3, 0x1d, 0xfd,
+        ];
+
+        for frame in [state, settings, stored_stats] {
+            assert_eq!(Response::parse(&frame).unwrap().encode(), frame);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+/*!
+    Borrowed, zero-copy views over response frames.
+
+    [`Response::parse`](crate::Response::parse) decodes every field up front and builds owned
+    structs. The views in this module only validate the framing of a response and then read
+    fields straight out of the underlying bytes when they are accessed, which is cheaper when a
+    consumer only cares about a couple of fields.
+
+    # Examples
+
+    ```rust
+    use walkingpad_protocol::view::ResponseView;
+
+    # let bytes = walkingpad_protocol::fixtures::STATE;
+    if let Ok(ResponseView::State(state)) = ResponseView::parse(&bytes) {
+        let _ = (state.motor_state(), state.speed());
+    }
+    ```
+*/
+
+use core::convert::TryFrom;
+use core::time::Duration;
+
+use super::response::{decameter_to_meter, MotorState, Settings, State, StoredStats};
+use super::{
+    Error, InfoFlags, Mode, Response, Result, Sensitivity, Speed, Subject, Units, MESSAGE_FOOTER,
+    RESPONSE_HEADER,
+};
+
+/// Every response carries a payload of the same size, regardless of its subject.
+const PAYLOAD_SIZE: usize = 16;
+
+/// Header and subject bytes before the payload.
+const PAYLOAD_OFFSET: usize = 2;
+
+/// Header, subject, payload, crc and footer.
+const FRAME_SIZE: usize = PAYLOAD_OFFSET + PAYLOAD_SIZE + 2;
+
+/// A validated response frame whose fields are decoded lazily.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ResponseView<'a> {
+    State(StateView<'a>),
+    Settings(SettingsView<'a>),
+    StoredStats(StoredStatsView<'a>),
+}
+
+impl<'a> ResponseView<'a> {
+    /// Checks the header, subject, length and footer of the frame without decoding the payload.
+    ///
+    /// Reports the same errors as [`Response::parse`] for malformed framing, but values inside
+    /// the payload, such as the mode or speed, are only validated by their accessors.
+    pub fn parse(bytes: &'a [u8]) -> Result<ResponseView<'a>> {
+        let header = *bytes.first().ok_or(Error::ResponseTooShort)?;
+        if header != RESPONSE_HEADER {
+            return Err(Error::InvalidResponseHeader(header));
+        }
+
+        let subject = bytes.get(1).ok_or(Error::ResponseTooShort)?;
+        let subject = Subject::try_from(*subject)?;
+
+        let footer = *bytes.get(FRAME_SIZE - 1).ok_or(Error::ResponseTooShort)?;
+        if footer != MESSAGE_FOOTER {
+            return Err(Error::InvalidResponseFooter(footer));
+        }
+
+        if bytes.len() > FRAME_SIZE {
+            return Err(Error::BytesAfterFooter);
+        }
+
+        let payload = bytes[PAYLOAD_OFFSET..PAYLOAD_OFFSET + PAYLOAD_SIZE]
+            .try_into()
+            .map(Payload)
+            .map_err(|_| Error::ResponseTooShort)?;
+        let view = match subject {
+            Subject::State => ResponseView::State(StateView(payload)),
+            Subject::Settings => ResponseView::Settings(SettingsView(payload)),
+            Subject::StoredStats => ResponseView::StoredStats(StoredStatsView(payload)),
+        };
+
+        Ok(view)
+    }
+
+    /// Decodes every field into an owned [`Response`].
+    pub fn to_response(&self) -> Result<Response> {
+        Ok(match self {
+            ResponseView::State(view) => State::try_from(*view)?.into(),
+            ResponseView::Settings(view) => Settings::try_from(*view)?.into(),
+            ResponseView::StoredStats(view) => StoredStats::from(*view).into(),
+        })
+    }
+}
+
+/// Borrowed view over the payload of a [`State`] response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StateView<'a>(Payload<'a>);
+
+impl StateView<'_> {
+    pub fn motor_state(&self) -> MotorState {
+        self.0.u8_at(0).into()
+    }
+
+    pub fn speed(&self) -> Result<Speed> {
+        Speed::try_from_hm_per_hour(self.0.u8_at(1))
+    }
+
+    pub fn mode(&self) -> Result<Mode> {
+        Mode::try_from(self.0.u8_at(2))
+    }
+
+    pub fn run_time(&self) -> Duration {
+        Duration::from_secs(self.0.u24_at(3).into())
+    }
+
+    /// The distance traveled during the current run, in meters.
+    pub fn distance(&self) -> u32 {
+        decameter_to_meter(self.0.u24_at(6))
+    }
+
+    pub fn nb_steps(&self) -> u32 {
+        self.0.u24_at(9)
+    }
+
+    pub fn unknown(&self) -> [u8; 4] {
+        self.0.array_at(12)
+    }
+}
+
+impl TryFrom<StateView<'_>> for State {
+    type Error = Error;
+
+    fn try_from(view: StateView<'_>) -> Result<State> {
+        Ok(State {
+            motor_state: view.motor_state(),
+            speed: view.speed()?,
+            mode: view.mode()?,
+            run_time: view.run_time(),
+            distance: view.distance(),
+            nb_steps: view.nb_steps(),
+            unknown: view.unknown(),
+        })
+    }
+}
+
+/// Borrowed view over the payload of a [`Settings`] response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SettingsView<'a>(Payload<'a>);
+
+impl SettingsView<'_> {
+    pub fn goal_type(&self) -> u8 {
+        self.0.u8_at(0)
+    }
+
+    pub fn goal(&self) -> u32 {
+        self.0.u24_at(1)
+    }
+
+    pub fn calibration(&self) -> u8 {
+        self.0.u8_at(4)
+    }
+
+    pub fn max_speed(&self) -> Result<Speed> {
+        Speed::try_from_hm_per_hour(self.0.u8_at(5))
+    }
+
+    pub fn start_speed(&self) -> Result<Speed> {
+        Speed::try_from_hm_per_hour(self.0.u8_at(6))
+    }
+
+    pub fn start_mode(&self) -> Result<Mode> {
+        Mode::try_from(self.0.u8_at(7))
+    }
+
+    pub fn sensitivity(&self) -> Result<Sensitivity> {
+        Sensitivity::try_from(self.0.u8_at(8))
+    }
+
+    pub fn display(&self) -> Result<InfoFlags> {
+        InfoFlags::try_from(self.0.u8_at(9))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.0.u8_at(10) != 0
+    }
+
+    pub fn units(&self) -> Result<Units> {
+        Units::try_from(self.0.u8_at(11))
+    }
+
+    pub fn unknown(&self) -> [u8; 4] {
+        self.0.array_at(12)
+    }
+}
+
+impl TryFrom<SettingsView<'_>> for Settings {
+    type Error = Error;
+
+    fn try_from(view: SettingsView<'_>) -> Result<Settings> {
+        Ok(Settings {
+            goal_type: view.goal_type(),
+            goal: view.goal(),
+            calibration: view.calibration(),
+            max_speed: view.max_speed()?,
+            start_speed: view.start_speed()?,
+            start_mode: view.start_mode()?,
+            sensitivity: view.sensitivity()?,
+            display: view.display()?,
+            is_locked: view.is_locked(),
+            units: view.units()?,
+            unknown: view.unknown(),
+        })
+    }
+}
+
+/// Borrowed view over the payload of a [`StoredStats`] response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StoredStatsView<'a>(Payload<'a>);
+
+impl StoredStatsView<'_> {
+    pub fn current_time(&self) -> u32 {
+        self.0.u24_at(0)
+    }
+
+    pub fn start_time(&self) -> u32 {
+        self.0.u24_at(3)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.0.u24_at(6).into())
+    }
+
+    /// The distance traveled during the run, in meters.
+    pub fn distance(&self) -> u32 {
+        decameter_to_meter(self.0.u24_at(9))
+    }
+
+    pub fn nb_steps(&self) -> u32 {
+        self.0.u24_at(12)
+    }
+
+    pub fn next_id(&self) -> Option<u8> {
+        match self.0.u8_at(15) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+}
+
+impl From<StoredStatsView<'_>> for StoredStats {
+    fn from(view: StoredStatsView<'_>) -> StoredStats {
+        StoredStats {
+            current_time: view.current_time(),
+            start_time: view.start_time(),
+            duration: view.duration(),
+            distance: view.distance(),
+            nb_steps: view.nb_steps(),
+            next_id: view.next_id(),
+        }
+    }
+}
+
+/// The payload of a response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Payload<'a>(&'a [u8; PAYLOAD_SIZE]);
+
+impl Payload<'_> {
+    fn u8_at(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    fn u24_at(&self, offset: usize) -> u32 {
+        // Same 3-bytes long counters as in `response::read_u32`
+        u32::from_be_bytes([0, self.0[offset], self.0[offset + 1], self.0[offset + 2]])
+    }
+
+    fn array_at(&self, offset: usize) -> [u8; 4] {
+        [
+            self.0[offset],
+            self.0[offset + 1],
+            self.0[offset + 2],
+            self.0[offset + 3],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{SETTINGS, STATE, STORED_STATS};
+
+    #[test]
+    fn test() {
+        for frame in [&STATE[..], &SETTINGS[..], &STORED_STATS[..]] {
+            let view = ResponseView::parse(frame).unwrap();
+            assert_eq!(view.to_response().unwrap(), Response::parse(frame).unwrap());
+        }
+
+        let ResponseView::State(state) = ResponseView::parse(&STATE).unwrap() else {
+            panic!("expected a state view");
+        };
+        assert_eq!(state.motor_state(), MotorState::Running);
+        assert_eq!(state.speed().unwrap(), Speed::from_hm_per_hour(25));
+        assert_eq!(state.run_time(), Duration::from_secs(300));
+        assert_eq!(state.distance(), 500);
+        assert_eq!(state.nb_steps(), 512);
+
+        let ResponseView::StoredStats(stats) = ResponseView::parse(&STORED_STATS).unwrap() else {
+            panic!("expected a stored stats view");
+        };
+        assert_eq!(stats.duration(), Duration::from_secs(7 * 256 + 8));
+        assert_eq!(stats.next_id(), Some(3));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(matches!(
+            ResponseView::parse(&STATE[..10]),
+            Err(Error::ResponseTooShort)
+        ));
+        let mut too_long = [0; FRAME_SIZE + 1];
+        too_long[..FRAME_SIZE].copy_from_slice(&STATE);
+        assert!(matches!(
+            ResponseView::parse(&too_long),
+            Err(Error::BytesAfterFooter)
+        ));
+        assert!(matches!(
+            ResponseView::parse(&[0xf7, 0xa2]),
+            Err(Error::InvalidResponseHeader(0xf7))
+        ));
+        assert!(matches!(
+            ResponseView::parse(&[0xf8, 0x01]),
+            Err(Error::InvalidType(0x01, "subject"))
+        ));
+
+        let mut bad_mode = STATE;
+        bad_mode[4] = 3;
+        let ResponseView::State(state) = ResponseView::parse(&bad_mode).unwrap() else {
+            panic!("expected a state view");
+        };
+        assert!(matches!(state.mode(), Err(Error::InvalidType(3, "mode"))));
+    }
+}
@@ -0,0 +1,103 @@
+//! Bluetooth client, available with the `btle` feature.
+
+use pyo3::exceptions::{PyConnectionError, PyValueError};
+use pyo3::prelude::*;
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use walkingpad_btle::{ConnectOptions, Target, Treadmill};
+use walkingpad_protocol::response;
+
+use crate::{Request, Settings, State, StoredStats};
+
+fn connection_error(err: walkingpad_btle::Error) -> PyErr {
+    PyConnectionError::new_err(err.to_string())
+}
+
+/// A connection to a treadmill, see `walkingpad_btle::Treadmill`.
+/// Every method blocks without holding the GIL.
+#[pyclass(frozen, module = "walkingpad")]
+pub struct WalkingPad {
+    treadmill: Box<dyn Treadmill>,
+    states: Mutex<Receiver<response::State>>,
+}
+
+#[pymethods]
+impl WalkingPad {
+    /// Connects to the treadmill described by `uri`, e.g. `"tcp://host:4242"` or `"sim://"` with
+    /// the `sim` feature, or to the first treadmill found.
+    #[staticmethod]
+    #[pyo3(signature = (uri=None))]
+    fn connect(py: Python<'_>, uri: Option<&str>) -> PyResult<WalkingPad> {
+        let target = match uri {
+            Some(uri) => uri
+                .parse::<Target>()
+                .map_err(|err| PyValueError::new_err(err.to_string()))?,
+            None => Target::Any,
+        };
+        let options = ConnectOptions {
+            target,
+            ..ConnectOptions::default()
+        };
+        let treadmill = py
+            .allow_threads(|| walkingpad_btle::connect_treadmill_with(options))
+            .map_err(connection_error)?;
+        let states = Mutex::new(treadmill.states());
+
+        Ok(WalkingPad { treadmill, states })
+    }
+
+    fn send(&self, py: Python<'_>, request: &Request) -> PyResult<()> {
+        let request = request.0.clone();
+        py.allow_threads(|| self.treadmill.send(request))
+            .map_err(connection_error)
+    }
+
+    fn start(&self, py: Python<'_>) -> PyResult<()> {
+        py.allow_threads(|| self.treadmill.start())
+            .map_err(connection_error)
+    }
+
+    fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        py.allow_threads(|| self.treadmill.stop())
+            .map_err(connection_error)
+    }
+
+    /// Polls the treadmill and waits up to `timeout` seconds for its state.
+    #[pyo3(signature = (timeout=1.0))]
+    fn state(&self, py: Python<'_>, timeout: f64) -> PyResult<Option<State>> {
+        py.allow_threads(|| {
+            let states = self.states.lock().unwrap();
+
+            // Only the answer to this poll is of interest
+            while states.try_recv().is_ok() {}
+            self.treadmill.poll_state().map_err(connection_error)?;
+
+            match states.recv_timeout(Duration::from_secs_f64(timeout)) {
+                Ok(state) => Ok(Some(State(state))),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    Err(connection_error(walkingpad_btle::Error::ConnectionClosed))
+                }
+            }
+        })
+    }
+
+    fn settings(&self, py: Python<'_>) -> PyResult<Settings> {
+        py.allow_threads(|| self.treadmill.settings())
+            .map(Settings)
+            .map_err(connection_error)
+    }
+
+    /// Retrieves the runs stored on the treadmill.
+    /// Raises `ConnectionError` if the transfer is interrupted.
+    fn history(&self, py: Python<'_>) -> PyResult<Vec<StoredStats>> {
+        let (stats, err) = py.allow_threads(|| self.treadmill.history());
+        match err {
+            Some(err) => Err(connection_error(err)),
+            None => Ok(stats.into_iter().map(StoredStats).collect()),
+        }
+    }
+}
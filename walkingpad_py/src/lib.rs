@@ -0,0 +1,422 @@
+/*!
+    Python bindings for the WalkingPad protocol, built with maturin.
+
+    ```python
+    import walkingpad
+
+    request = walkingpad.Request.set_speed(25)
+    bytes(request)  # to be written to the WalkingPad
+
+    state = walkingpad.parse_response(notification)
+    if isinstance(state, walkingpad.State):
+        print(state.distance, state.run_time)
+    ```
+
+    With the `btle` feature, `walkingpad.WalkingPad.connect()` also drives a pad over Bluetooth.
+*/
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use std::time::Duration;
+
+use walkingpad_protocol as protocol;
+use walkingpad_protocol::request;
+
+#[cfg(feature = "btle")]
+mod client;
+
+fn value_error(err: protocol::Error) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn speed(hm_per_hour: u8) -> PyResult<protocol::Speed> {
+    protocol::Speed::try_from_hm_per_hour(hm_per_hour).map_err(value_error)
+}
+
+#[pyclass(eq, eq_int, frozen, module = "walkingpad")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    Auto = 0,
+    Manual = 1,
+    Sleep = 2,
+    Calibration = 4,
+}
+
+impl From<protocol::Mode> for Mode {
+    fn from(mode: protocol::Mode) -> Mode {
+        match mode {
+            protocol::Mode::Auto => Mode::Auto,
+            protocol::Mode::Manual => Mode::Manual,
+            protocol::Mode::Sleep => Mode::Sleep,
+            protocol::Mode::Calibration => Mode::Calibration,
+        }
+    }
+}
+
+impl From<Mode> for protocol::Mode {
+    fn from(mode: Mode) -> protocol::Mode {
+        match mode {
+            Mode::Auto => protocol::Mode::Auto,
+            Mode::Manual => protocol::Mode::Manual,
+            Mode::Sleep => protocol::Mode::Sleep,
+            Mode::Calibration => protocol::Mode::Calibration,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int, frozen, module = "walkingpad")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sensitivity {
+    High = 1,
+    Medium = 2,
+    Low = 3,
+}
+
+impl From<protocol::Sensitivity> for Sensitivity {
+    fn from(sensitivity: protocol::Sensitivity) -> Sensitivity {
+        match sensitivity {
+            protocol::Sensitivity::High => Sensitivity::High,
+            protocol::Sensitivity::Medium => Sensitivity::Medium,
+            protocol::Sensitivity::Low => Sensitivity::Low,
+        }
+    }
+}
+
+impl From<Sensitivity> for protocol::Sensitivity {
+    fn from(sensitivity: Sensitivity) -> protocol::Sensitivity {
+        match sensitivity {
+            Sensitivity::High => protocol::Sensitivity::High,
+            Sensitivity::Medium => protocol::Sensitivity::Medium,
+            Sensitivity::Low => protocol::Sensitivity::Low,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int, frozen, module = "walkingpad")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Units {
+    Metric = 0,
+    Imperial = 1,
+}
+
+impl From<protocol::Units> for Units {
+    fn from(units: protocol::Units) -> Units {
+        match units {
+            protocol::Units::Metric => Units::Metric,
+            protocol::Units::Imperial => Units::Imperial,
+        }
+    }
+}
+
+impl From<Units> for protocol::Units {
+    fn from(units: Units) -> protocol::Units {
+        match units {
+            Units::Metric => protocol::Units::Metric,
+            Units::Imperial => protocol::Units::Imperial,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int, frozen, module = "walkingpad")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MotorState {
+    Stopped,
+    Running,
+    Starting,
+    Unknown,
+}
+
+impl From<protocol::response::MotorState> for MotorState {
+    fn from(motor_state: protocol::response::MotorState) -> MotorState {
+        use protocol::response::MotorState::*;
+
+        match motor_state {
+            Stopped => MotorState::Stopped,
+            Running => MotorState::Running,
+            Starting => MotorState::Starting,
+            Unknown(_) => MotorState::Unknown,
+        }
+    }
+}
+
+/// A request to write to the WalkingPad, `bytes(request)` gives its encoding.
+/// Speeds are in hectometers per hour, from 0 to 60.
+#[pyclass(eq, frozen, module = "walkingpad")]
+#[derive(Clone, PartialEq)]
+pub struct Request(pub(crate) protocol::Request);
+
+#[pymethods]
+impl Request {
+    #[staticmethod]
+    fn start() -> Request {
+        Request(request::start())
+    }
+
+    #[staticmethod]
+    fn stop() -> Request {
+        Request(request::stop())
+    }
+
+    /// Clears all data associated with past runs stored on the WalkingPad.
+    #[staticmethod]
+    fn clear_stats() -> Request {
+        Request(request::clear_stats())
+    }
+
+    #[staticmethod]
+    fn get_state() -> Request {
+        Request(request::get::state())
+    }
+
+    #[staticmethod]
+    fn get_settings() -> Request {
+        Request(request::get::settings())
+    }
+
+    #[staticmethod]
+    fn get_latest_stored_stats() -> Request {
+        Request(request::get::latest_stored_stats())
+    }
+
+    #[staticmethod]
+    fn get_stored_stats(id: u8) -> Request {
+        Request(request::get::stored_stats(id))
+    }
+
+    #[staticmethod]
+    fn set_speed(hm_per_hour: u8) -> PyResult<Request> {
+        Ok(Request(request::set::speed(speed(hm_per_hour)?)))
+    }
+
+    #[staticmethod]
+    fn set_mode(mode: Mode) -> Request {
+        Request(request::set::mode(mode.into()))
+    }
+
+    #[staticmethod]
+    fn set_calibration_mode(enabled: bool) -> Request {
+        Request(request::set::calibration_mode(enabled))
+    }
+
+    #[staticmethod]
+    fn set_max_speed(hm_per_hour: u8) -> PyResult<Request> {
+        Ok(Request(request::set::max_speed(speed(hm_per_hour)?)))
+    }
+
+    #[staticmethod]
+    fn set_start_speed(hm_per_hour: u8) -> PyResult<Request> {
+        Ok(Request(request::set::start_speed(speed(hm_per_hour)?)))
+    }
+
+    #[staticmethod]
+    fn set_auto_start(enabled: bool) -> Request {
+        Request(request::set::auto_start(enabled))
+    }
+
+    #[staticmethod]
+    fn set_sensitivity(sensitivity: Sensitivity) -> Request {
+        Request(request::set::sensitivity(sensitivity.into()))
+    }
+
+    /// Takes the bit flags of the displayed statistics: time, speed, distance, calorie and step,
+    /// from the least significant bit.
+    #[staticmethod]
+    fn set_display(flags: u8) -> PyResult<Request> {
+        let flags = protocol::InfoFlags::try_from(flags).map_err(value_error)?;
+        Ok(Request(request::set::display(flags)))
+    }
+
+    #[staticmethod]
+    fn set_units(units: Units) -> Request {
+        Request(request::set::units(units.into()))
+    }
+
+    #[staticmethod]
+    fn set_locked(is_locked: bool) -> Request {
+        Request(request::set::locked(is_locked))
+    }
+
+    fn __bytes__(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// The current state of the WalkingPad.
+#[pyclass(frozen, eq, module = "walkingpad")]
+#[derive(Clone, PartialEq)]
+pub struct State(pub(crate) protocol::response::State);
+
+#[pymethods]
+impl State {
+    #[getter]
+    fn motor_state(&self) -> MotorState {
+        self.0.motor_state.into()
+    }
+
+    /// In hectometers per hour.
+    #[getter]
+    fn speed(&self) -> u8 {
+        self.0.speed.hm_per_hour()
+    }
+
+    #[getter]
+    fn mode(&self) -> Mode {
+        self.0.mode.into()
+    }
+
+    #[getter]
+    fn run_time(&self) -> Duration {
+        self.0.run_time
+    }
+
+    /// In meters.
+    #[getter]
+    fn distance(&self) -> u32 {
+        self.0.distance
+    }
+
+    #[getter]
+    fn nb_steps(&self) -> u32 {
+        self.0.nb_steps
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// The settings stored on the WalkingPad.
+#[pyclass(frozen, eq, module = "walkingpad")]
+#[derive(Clone, PartialEq)]
+pub struct Settings(pub(crate) protocol::response::Settings);
+
+#[pymethods]
+impl Settings {
+    /// In hectometers per hour.
+    #[getter]
+    fn max_speed(&self) -> u8 {
+        self.0.max_speed.hm_per_hour()
+    }
+
+    /// In hectometers per hour.
+    #[getter]
+    fn start_speed(&self) -> u8 {
+        self.0.start_speed.hm_per_hour()
+    }
+
+    #[getter]
+    fn start_mode(&self) -> Mode {
+        self.0.start_mode.into()
+    }
+
+    #[getter]
+    fn sensitivity(&self) -> Sensitivity {
+        self.0.sensitivity.into()
+    }
+
+    /// Bit flags, see `Request.set_display`.
+    #[getter]
+    fn display(&self) -> u8 {
+        self.0.display.bits()
+    }
+
+    #[getter]
+    fn is_locked(&self) -> bool {
+        self.0.is_locked
+    }
+
+    #[getter]
+    fn units(&self) -> Units {
+        self.0.units.into()
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// The statistics of a past run stored on the WalkingPad.
+#[pyclass(frozen, eq, module = "walkingpad")]
+#[derive(Clone, PartialEq)]
+pub struct StoredStats(pub(crate) protocol::response::StoredStats);
+
+#[pymethods]
+impl StoredStats {
+    #[getter]
+    fn current_time(&self) -> u32 {
+        self.0.current_time
+    }
+
+    #[getter]
+    fn start_time(&self) -> u32 {
+        self.0.start_time
+    }
+
+    #[getter]
+    fn duration(&self) -> Duration {
+        self.0.duration
+    }
+
+    /// In meters.
+    #[getter]
+    fn distance(&self) -> u32 {
+        self.0.distance
+    }
+
+    #[getter]
+    fn nb_steps(&self) -> u32 {
+        self.0.nb_steps
+    }
+
+    #[getter]
+    fn next_id(&self) -> Option<u8> {
+        self.0.next_id
+    }
+
+    fn __repr__(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+pub(crate) fn response_into_py(py: Python<'_>, response: protocol::Response) -> PyResult<PyObject> {
+    Ok(match response {
+        protocol::Response::State(inner) => State(inner).into_pyobject(py)?.into_any().unbind(),
+        protocol::Response::Settings(inner) => {
+            Settings(inner).into_pyobject(py)?.into_any().unbind()
+        }
+        protocol::Response::StoredStats(inner) => {
+            StoredStats(inner).into_pyobject(py)?.into_any().unbind()
+        }
+    })
+}
+
+/// Parses a notification from the WalkingPad into a `State`, `Settings` or `StoredStats`.
+/// Raises `ValueError` when the notification is malformed.
+#[pyfunction]
+fn parse_response(py: Python<'_>, data: &[u8]) -> PyResult<PyObject> {
+    let response = protocol::Response::parse(data).map_err(value_error)?;
+    response_into_py(py, response)
+}
+
+#[pymodule]
+fn walkingpad(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Mode>()?;
+    m.add_class::<Sensitivity>()?;
+    m.add_class::<Units>()?;
+    m.add_class::<MotorState>()?;
+    m.add_class::<Request>()?;
+    m.add_class::<State>()?;
+    m.add_class::<Settings>()?;
+    m.add_class::<StoredStats>()?;
+    m.add_function(wrap_pyfunction!(parse_response, m)?)?;
+
+    #[cfg(feature = "btle")]
+    m.add_class::<client::WalkingPad>()?;
+
+    Ok(())
+}
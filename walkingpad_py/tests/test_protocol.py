@@ -0,0 +1,96 @@
+import json
+import os
+from datetime import timedelta
+
+import pytest
+
+import walkingpad
+
+# No frames recorded from a treadmill are available yet, these are built after the WalkingPad's
+# frame layout, the checksum being the wrapping sum of the bytes between the header and itself.
+# Recorded frames are checked by test_recorded_capture, see below.
+STATE = bytes.fromhex("f8a20119010001 2c 000032 000200 00000000 1e fd".replace(" ", ""))
+SETTINGS = bytes.fromhex("f8a600000000003c14010203010000000000 fd fd".replace(" ", ""))
+STORED_STATS = bytes.fromhex("f8a7000100 0000c8 000708 000064 000400 03 ea fd".replace(" ", ""))
+
+
+def test_fixture_checksums():
+    for frame in (STATE, SETTINGS, STORED_STATS):
+        assert frame[-2] == sum(frame[1:-2]) % 256
+
+
+def test_requests():
+    assert bytes(walkingpad.Request.start()) == bytes([0xF7, 0xA2, 0x04, 0x01, 0xA7, 0xFD])
+    assert bytes(walkingpad.Request.set_speed(25))[3] == 25
+    assert len(bytes(walkingpad.Request.get_settings())) == 9
+    assert walkingpad.Request.set_mode(walkingpad.Mode.Manual) == walkingpad.Request.set_mode(
+        walkingpad.Mode.Manual
+    )
+
+    with pytest.raises(ValueError):
+        walkingpad.Request.set_speed(61)
+
+
+def test_parse_state():
+    state = walkingpad.parse_response(STATE)
+
+    assert isinstance(state, walkingpad.State)
+    assert state.motor_state == walkingpad.MotorState.Running
+    assert state.speed == 25
+    assert state.mode == walkingpad.Mode.Manual
+    assert state.run_time == timedelta(seconds=300)
+    assert state.distance == 500
+    assert state.nb_steps == 512
+
+
+def test_parse_settings():
+    settings = walkingpad.parse_response(SETTINGS)
+
+    assert isinstance(settings, walkingpad.Settings)
+    assert settings.max_speed == 60
+    assert settings.start_speed == 20
+    assert settings.start_mode == walkingpad.Mode.Manual
+    assert settings.sensitivity == walkingpad.Sensitivity.Medium
+    assert settings.units == walkingpad.Units.Metric
+
+
+def test_parse_stored_stats():
+    stats = walkingpad.parse_response(STORED_STATS)
+
+    assert isinstance(stats, walkingpad.StoredStats)
+    assert stats.start_time == 200
+    assert stats.duration == timedelta(seconds=7 * 256 + 8)
+    assert stats.distance == 1000
+    assert stats.next_id == 3
+
+
+def test_parse_malformed():
+    with pytest.raises(ValueError):
+        walkingpad.parse_response(STATE[:10])
+
+    with pytest.raises(ValueError):
+        walkingpad.parse_response(STATE + b"\x00")
+
+
+# The WalkingPad's notify characteristic
+WALKINGPAD_NOTIFY = "0000fe01-0000-1000-8000-00805f9b34fb"
+
+
+@pytest.mark.skipif(
+    "WALKINGPAD_CAPTURE" not in os.environ,
+    reason="set WALKINGPAD_CAPTURE to a capture recorded with `crabwalk --capture`",
+)
+def test_recorded_capture():
+    with open(os.environ["WALKINGPAD_CAPTURE"]) as capture:
+        records = [json.loads(line) for line in capture if line.strip()]
+
+    frames = [
+        bytes(record["data"])
+        for record in records
+        if record["kind"] == "notify" and record["characteristic"] == WALKINGPAD_NOTIFY
+    ]
+    assert frames, "the capture holds no WalkingPad notification"
+
+    for frame in frames:
+        assert frame[-2] == sum(frame[1:-2]) % 256
+        walkingpad.parse_response(frame)
@@ -0,0 +1,411 @@
+/*!
+    A virtual WalkingPad, answering requests the way the device does, to develop and test
+    without the treadmill nearby.
+
+    The simulator doesn't keep time on its own, it only moves forward when told how much time
+    has passed, so that tests can run as fast as they like.
+
+    ```rust
+    use std::time::Duration;
+    use walkingpad_protocol::{request, Response};
+    use walkingpad_sim::Simulator;
+
+    let mut simulator = Simulator::new();
+    simulator.handle(request::start().as_bytes()).unwrap();
+    simulator.advance(Duration::from_secs(10));
+
+    let answer = simulator.handle(request::get::state().as_bytes()).unwrap();
+    assert!(matches!(Response::parse(&answer.unwrap()), Ok(Response::State(_))));
+    ```
+*/
+
+use std::time::Duration;
+
+use walkingpad_protocol::request::Command;
+use walkingpad_protocol::response::{MotorState, Settings, State, StoredStats, ENCODED_LEN};
+use walkingpad_protocol::{Error, InfoFlags, Mode, Request, Response, Sensitivity, Speed, Units};
+
+/// How long the WalkingPad counts down before the belt starts moving.
+pub const START_DELAY: Duration = Duration::from_secs(3);
+
+/// How fast the belt speeds up and slows down, in hm/h per second.
+pub const ACCELERATION: f64 = 5.0;
+
+/// The length of a step, in meters.
+pub const STRIDE: f64 = 0.6;
+
+/// How many runs are kept, the oldest ones being dropped past that.
+/// The ids 0 and 255 stand for clearing and for the latest run.
+pub const MAX_STORED_RUNS: usize = 200;
+
+/// The speed the belt keeps in the Calibration mode.
+const CALIBRATION_SPEED: f64 = 40.0;
+
+/// The simulation is integrated in steps no longer than this.
+const STEP: Duration = Duration::from_millis(100);
+
+/// A virtual WalkingPad, see the [crate's documentation](crate).
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    state: State,
+    settings: Settings,
+    /// From the oldest to the latest.
+    runs: Vec<StoredStats>,
+    /// The internal clock, which only ticks while the belt is running.
+    clock: Duration,
+    run_start: Duration,
+    countdown: Duration,
+    is_stopping: bool,
+    /// In hm/h, kept fractional so that short steps still ramp up.
+    belt_speed: f64,
+    target_speed: f64,
+    /// In meters, kept fractional so that short steps still add up.
+    distance: f64,
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::with_settings(Settings {
+            goal_type: 0,
+            goal: 0,
+            calibration: 0,
+            max_speed: Speed::from_hm_per_hour(60),
+            start_speed: Speed::default(),
+            start_mode: Mode::Manual,
+            sensitivity: Sensitivity::Medium,
+            display: InfoFlags::TIME | InfoFlags::SPEED | InfoFlags::DISTANCE | InfoFlags::STEP,
+            is_locked: false,
+            units: Units::Metric,
+            unknown: [0; 4],
+        })
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator::default()
+    }
+
+    /// Boots up with the settings, in their start mode.
+    pub fn with_settings(settings: Settings) -> Simulator {
+        Simulator {
+            state: State {
+                motor_state: MotorState::Stopped,
+                speed: Speed::from_hm_per_hour(0),
+                mode: settings.start_mode,
+                run_time: Duration::ZERO,
+                distance: 0,
+                nb_steps: 0,
+                unknown: [0; 4],
+            },
+            settings,
+            runs: vec![],
+            clock: Duration::ZERO,
+            run_start: Duration::ZERO,
+            countdown: Duration::ZERO,
+            is_stopping: false,
+            belt_speed: 0.0,
+            target_speed: 0.0,
+            distance: 0.0,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The stored runs, from the oldest to the latest, chained from the latest by their
+    /// `next_id`.
+    pub fn stored_runs(&self) -> Vec<StoredStats> {
+        (1..=self.runs.len() as u8)
+            .map(|id| self.stored_stats(id))
+            .collect()
+    }
+
+    /// Stores a run as if it just ended, e.g. to have some history to sync.
+    /// The distance is rounded down to decameters, like the WalkingPad's.
+    pub fn store_run(&mut self, duration: Duration, distance: u32, nb_steps: u32) {
+        if self.runs.len() == MAX_STORED_RUNS {
+            self.runs.remove(0);
+        }
+
+        self.runs.push(StoredStats {
+            current_time: 0,
+            start_time: self.clock.saturating_sub(duration).as_secs() as u32,
+            duration,
+            distance: distance / 10 * 10,
+            nb_steps,
+            next_id: None,
+        });
+    }
+
+    /// Handles the bytes written by a client, returning the bytes of the answer if the request
+    /// calls for one.
+    pub fn handle(&mut self, bytes: &[u8]) -> Result<Option<[u8; ENCODED_LEN]>, Error> {
+        let command = Request::parse(bytes)?.command()?;
+        Ok(self.apply(command).map(|response| response.encode()))
+    }
+
+    /// Carries out the command, returning the answer if it calls for one.
+    pub fn apply(&mut self, command: Command) -> Option<Response> {
+        let is_moving = self.state.motor_state != MotorState::Stopped && !self.is_stopping;
+
+        match command {
+            Command::GetState => return Some(self.state.clone().into()),
+            Command::GetSettings => return Some(self.settings.clone().into()),
+            Command::GetLatestStoredStats => {
+                return Some(self.stored_stats(self.runs.len() as u8).into())
+            }
+            Command::GetStoredStats(id) => return Some(self.stored_stats(id).into()),
+            Command::ClearStats => self.runs.clear(),
+            Command::Start => {
+                if self.state.motor_state == MotorState::Stopped && self.state.mode != Mode::Sleep {
+                    self.start();
+                }
+            }
+            Command::Stop => self.stop(),
+            Command::SetSpeed(speed) => {
+                // In the Automatic mode, the belt follows whoever walks on it
+                if is_moving && self.state.mode == Mode::Manual {
+                    self.target_speed = self.capped(speed);
+                }
+            }
+            Command::SetMode(mode) => {
+                self.state.mode = mode;
+                match mode {
+                    Mode::Sleep => self.stop(),
+                    Mode::Calibration if is_moving => self.target_speed = CALIBRATION_SPEED,
+                    _ => {}
+                }
+            }
+            Command::SetCalibrationMode(enabled) => self.settings.calibration = enabled as u8,
+            Command::SetMaxSpeed(speed) => {
+                self.settings.max_speed = speed;
+                self.target_speed = self.target_speed.min(speed.hm_per_hour().into());
+            }
+            Command::SetStartSpeed(speed) => self.settings.start_speed = speed,
+            // Not part of the settings the WalkingPad reports
+            Command::SetAutoStart(_) => {}
+            Command::SetSensitivity(sensitivity) => self.settings.sensitivity = sensitivity,
+            Command::SetDisplay(flags) => self.settings.display = flags,
+            Command::SetUnits(units) => self.settings.units = units,
+            Command::SetLocked(is_locked) => self.settings.is_locked = is_locked,
+        }
+
+        None
+    }
+
+    /// Moves the simulation forward.
+    pub fn advance(&mut self, elapsed: Duration) {
+        let mut left = elapsed;
+
+        while !left.is_zero() {
+            let step = left.min(STEP);
+            self.step(step);
+            left -= step;
+        }
+    }
+
+    fn step(&mut self, elapsed: Duration) {
+        match self.state.motor_state {
+            MotorState::Starting => {
+                self.countdown = self.countdown.saturating_sub(elapsed);
+                if self.countdown.is_zero() {
+                    self.state.motor_state = MotorState::Running;
+                    self.run_start = self.clock;
+                }
+            }
+            MotorState::Running => {
+                let seconds = elapsed.as_secs_f64();
+
+                let ramp = ACCELERATION * seconds;
+                let gap = self.target_speed - self.belt_speed;
+                self.belt_speed += gap.clamp(-ramp, ramp);
+
+                // From hm/h to m/s
+                self.distance += self.belt_speed * 100.0 / 3600.0 * seconds;
+                self.clock += elapsed;
+                self.state.run_time += elapsed;
+
+                self.state.speed = Speed::from_hm_per_hour(self.belt_speed.round() as u8);
+                // The WalkingPad counts in decameters
+                self.state.distance = (self.distance / 10.0) as u32 * 10;
+                self.state.nb_steps = (self.distance / STRIDE) as u32;
+
+                if self.is_stopping && self.belt_speed <= 0.0 {
+                    self.finish_run();
+                }
+            }
+            MotorState::Stopped | MotorState::Unknown(_) => {}
+        }
+    }
+
+    fn start(&mut self) {
+        self.state.motor_state = MotorState::Starting;
+        self.state.run_time = Duration::ZERO;
+        self.state.distance = 0;
+        self.state.nb_steps = 0;
+        self.distance = 0.0;
+        self.countdown = START_DELAY;
+        self.is_stopping = false;
+        self.target_speed = match self.state.mode {
+            Mode::Calibration => CALIBRATION_SPEED,
+            _ => self.capped(self.settings.start_speed),
+        };
+    }
+
+    /// The belt slows down to a halt before the run is over.
+    fn stop(&mut self) {
+        match self.state.motor_state {
+            MotorState::Starting => self.state.motor_state = MotorState::Stopped,
+            MotorState::Running => {
+                self.is_stopping = true;
+                self.target_speed = 0.0;
+            }
+            MotorState::Stopped | MotorState::Unknown(_) => {}
+        }
+    }
+
+    fn finish_run(&mut self) {
+        self.state.motor_state = MotorState::Stopped;
+        self.state.speed = Speed::from_hm_per_hour(0);
+        self.is_stopping = false;
+        self.belt_speed = 0.0;
+
+        if self.state.run_time.as_secs() > 0 {
+            if self.runs.len() == MAX_STORED_RUNS {
+                self.runs.remove(0);
+            }
+
+            self.runs.push(StoredStats {
+                current_time: 0,
+                start_time: self.run_start.as_secs() as u32,
+                duration: Duration::from_secs(self.state.run_time.as_secs()),
+                distance: self.state.distance,
+                nb_steps: self.state.nb_steps,
+                next_id: None,
+            });
+        }
+    }
+
+    /// The run with the id, each run pointing to the one before it.
+    /// Unknown ids are answered with a blank record, like an empty history.
+    fn stored_stats(&self, id: u8) -> StoredStats {
+        let current_time = self.clock.as_secs() as u32;
+
+        match (id as usize).checked_sub(1).and_then(|i| self.runs.get(i)) {
+            Some(run) => StoredStats {
+                current_time,
+                next_id: (id > 1).then_some(id - 1),
+                ..run.clone()
+            },
+            None => StoredStats {
+                current_time,
+                start_time: 0,
+                duration: Duration::ZERO,
+                distance: 0,
+                nb_steps: 0,
+                next_id: None,
+            },
+        }
+    }
+
+    fn capped(&self, speed: Speed) -> f64 {
+        let max_speed = self.settings.max_speed.hm_per_hour();
+        speed.hm_per_hour().min(max_speed).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use walkingpad_protocol::request;
+
+    fn speed(hm_per_hour: u8) -> Speed {
+        Speed::from_hm_per_hour(hm_per_hour)
+    }
+
+    #[test]
+    fn test() {
+        let mut simulator = Simulator::new();
+
+        simulator.apply(Command::Start);
+        assert_eq!(simulator.state().motor_state, MotorState::Starting);
+        simulator.advance(START_DELAY);
+        assert_eq!(simulator.state().motor_state, MotorState::Running);
+
+        simulator.advance(Duration::from_secs(10));
+        assert_eq!(simulator.state().speed, Speed::default());
+
+        simulator.apply(Command::SetSpeed(speed(30)));
+        simulator.advance(Duration::from_secs(1));
+        assert_eq!(simulator.state().speed, speed(25));
+        simulator.advance(Duration::from_secs(60));
+        assert_eq!(simulator.state().speed, speed(30));
+        assert_eq!(simulator.state().distance, 50);
+        assert_eq!(
+            simulator.state().nb_steps,
+            (simulator.distance / STRIDE) as u32
+        );
+
+        simulator.apply(Command::Stop);
+        simulator.advance(Duration::from_secs(1));
+        assert_eq!(simulator.state().motor_state, MotorState::Running);
+        simulator.advance(Duration::from_secs(10));
+        assert_eq!(simulator.state().motor_state, MotorState::Stopped);
+
+        // The belt isn't driven by requests in the Automatic mode
+        simulator.apply(Command::SetMode(Mode::Auto));
+        simulator.apply(Command::Start);
+        simulator.advance(START_DELAY + Duration::from_secs(10));
+        simulator.apply(Command::SetSpeed(speed(50)));
+        simulator.advance(Duration::from_secs(10));
+        assert_eq!(simulator.state().speed, speed(20));
+
+        // Sleeping stops the belt
+        simulator.apply(Command::SetMode(Mode::Sleep));
+        simulator.advance(Duration::from_secs(10));
+        assert_eq!(simulator.state().motor_state, MotorState::Stopped);
+        simulator.apply(Command::Start);
+        assert_eq!(simulator.state().motor_state, MotorState::Stopped);
+
+        let runs = simulator.stored_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].next_id, None);
+        assert_eq!(runs[1].next_id, Some(1));
+        assert!(runs[1].start_time > runs[0].start_time);
+
+        let answer = simulator
+            .handle(request::get::latest_stored_stats().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Response::parse(&answer).unwrap(),
+            Response::StoredStats(runs[1].clone())
+        );
+
+        assert_eq!(
+            simulator.handle(request::clear_stats().as_bytes()).unwrap(),
+            None
+        );
+        assert!(simulator.stored_runs().is_empty());
+
+        simulator
+            .handle(request::set::max_speed(speed(40)).as_bytes())
+            .unwrap();
+        let answer = simulator
+            .handle(request::get::settings().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            Response::parse(&answer).unwrap(),
+            Response::Settings(s) if s.max_speed == speed(40)
+        ));
+
+        assert!(simulator.handle(&[0xf7, 0xa2]).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+    WebAssembly bindings for the WalkingPad protocol.
+
+    The functions exported to JavaScript build the bytes of requests, to be written to the
+    WalkingPad's write characteristic, and parse the notifications of its read characteristic
+    into plain objects. The Bluetooth connection itself is left to the browser's Web Bluetooth
+    API:
+
+    ```js
+    import * as walkingpad from "walkingpad_wasm";
+
+    const device = await navigator.bluetooth.requestDevice({
+      filters: [{ services: [walkingpad.serviceUuid()] }],
+    });
+    const server = await device.gatt.connect();
+    const service = await server.getPrimaryService(walkingpad.serviceUuid());
+    const write = await service.getCharacteristic(walkingpad.writeCharacteristicUuid());
+    const notify = await service.getCharacteristic(walkingpad.notifyCharacteristicUuid());
+
+    notify.addEventListener("characteristicvaluechanged", (event) => {
+      const bytes = new Uint8Array(event.target.value.buffer);
+      console.log(walkingpad.parseResponse(bytes));
+    });
+    await notify.startNotifications();
+
+    await write.writeValueWithoutResponse(walkingpad.getState());
+    ```
+*/
+
+use serde::Serialize;
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{Settings, State, StoredStats};
+use walkingpad_protocol::{InfoFlags, Mode, Response, Sensitivity, Speed, Units};
+use wasm_bindgen::prelude::*;
+
+/// Web Bluetooth expects the full 128-bit form of the 16-bit UUIDs.
+fn full_uuid(uuid: u16) -> String {
+    format!("0000{:04x}-0000-1000-8000-00805f9b34fb", uuid)
+}
+
+#[wasm_bindgen(js_name = serviceUuid)]
+pub fn service_uuid() -> String {
+    full_uuid(0xfe00)
+}
+
+#[wasm_bindgen(js_name = writeCharacteristicUuid)]
+pub fn write_characteristic_uuid() -> String {
+    full_uuid(0xfe02)
+}
+
+#[wasm_bindgen(js_name = notifyCharacteristicUuid)]
+pub fn notify_characteristic_uuid() -> String {
+    full_uuid(0xfe01)
+}
+
+fn protocol_error(err: walkingpad_protocol::Error) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+fn speed(hm_per_hour: u8) -> Result<Speed, JsError> {
+    Speed::try_from_hm_per_hour(hm_per_hour).map_err(protocol_error)
+}
+
+fn from_js<T: serde::de::DeserializeOwned>(value: JsValue) -> Result<T, JsError> {
+    serde_wasm_bindgen::from_value(value).map_err(|err| JsError::new(&err.to_string()))
+}
+
+#[wasm_bindgen]
+pub fn start() -> Vec<u8> {
+    request::start().as_bytes().to_vec()
+}
+
+#[wasm_bindgen]
+pub fn stop() -> Vec<u8> {
+    request::stop().as_bytes().to_vec()
+}
+
+/// Clears all data associated with past runs stored on the WalkingPad.
+#[wasm_bindgen(js_name = clearStats)]
+pub fn clear_stats() -> Vec<u8> {
+    request::clear_stats().as_bytes().to_vec()
+}
+
+#[wasm_bindgen(js_name = getState)]
+pub fn get_state() -> Vec<u8> {
+    request::get::state().as_bytes().to_vec()
+}
+
+#[wasm_bindgen(js_name = getSettings)]
+pub fn get_settings() -> Vec<u8> {
+    request::get::settings().as_bytes().to_vec()
+}
+
+#[wasm_bindgen(js_name = getLatestStoredStats)]
+pub fn get_latest_stored_stats() -> Vec<u8> {
+    request::get::latest_stored_stats().as_bytes().to_vec()
+}
+
+#[wasm_bindgen(js_name = getStoredStats)]
+pub fn get_stored_stats(id: u8) -> Vec<u8> {
+    request::get::stored_stats(id).as_bytes().to_vec()
+}
+
+/// Takes the speed in hectometers per hour, from 0 to 60.
+#[wasm_bindgen(js_name = setSpeed)]
+pub fn set_speed(hm_per_hour: u8) -> Result<Vec<u8>, JsError> {
+    Ok(request::set::speed(speed(hm_per_hour)?).as_bytes().to_vec())
+}
+
+/// Takes the mode's name, e.g. `"Manual"`.
+#[wasm_bindgen(js_name = setMode)]
+pub fn set_mode(mode: JsValue) -> Result<Vec<u8>, JsError> {
+    let mode: Mode = from_js(mode)?;
+    Ok(request::set::mode(mode).as_bytes().to_vec())
+}
+
+#[wasm_bindgen(js_name = setCalibrationMode)]
+pub fn set_calibration_mode(enabled: bool) -> Vec<u8> {
+    request::set::calibration_mode(enabled).as_bytes().to_vec()
+}
+
+/// Takes the speed in hectometers per hour, from 0 to 60.
+#[wasm_bindgen(js_name = setMaxSpeed)]
+pub fn set_max_speed(hm_per_hour: u8) -> Result<Vec<u8>, JsError> {
+    Ok(request::set::max_speed(speed(hm_per_hour)?)
+        .as_bytes()
+        .to_vec())
+}
+
+/// Takes the speed in hectometers per hour, from 0 to 60.
+#[wasm_bindgen(js_name = setStartSpeed)]
+pub fn set_start_speed(hm_per_hour: u8) -> Result<Vec<u8>, JsError> {
+    Ok(request::set::start_speed(speed(hm_per_hour)?)
+        .as_bytes()
+        .to_vec())
+}
+
+#[wasm_bindgen(js_name = setAutoStart)]
+pub fn set_auto_start(enabled: bool) -> Vec<u8> {
+    request::set::auto_start(enabled).as_bytes().to_vec()
+}
+
+/// Takes the sensitivity's name, e.g. `"High"`.
+#[wasm_bindgen(js_name = setSensitivity)]
+pub fn set_sensitivity(sensitivity: JsValue) -> Result<Vec<u8>, JsError> {
+    let sensitivity: Sensitivity = from_js(sensitivity)?;
+    Ok(request::set::sensitivity(sensitivity).as_bytes().to_vec())
+}
+
+/// Takes the displayed statistics as a string of flags, e.g. `"TIME | SPEED"`.
+#[wasm_bindgen(js_name = setDisplay)]
+pub fn set_display(flags: JsValue) -> Result<Vec<u8>, JsError> {
+    let flags: InfoFlags = from_js(flags)?;
+    Ok(request::set::display(flags).as_bytes().to_vec())
+}
+
+/// Takes the units' name, e.g. `"Metric"`.
+#[wasm_bindgen(js_name = setUnits)]
+pub fn set_units(units: JsValue) -> Result<Vec<u8>, JsError> {
+    let units: Units = from_js(units)?;
+    Ok(request::set::units(units).as_bytes().to_vec())
+}
+
+#[wasm_bindgen(js_name = setLocked)]
+pub fn set_locked(is_locked: bool) -> Vec<u8> {
+    request::set::locked(is_locked).as_bytes().to_vec()
+}
+
+/// Mirrors [`Response`], with the kind of response in a `type` field.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum JsResponse {
+    State(State),
+    Settings(Settings),
+    StoredStats(StoredStats),
+}
+
+impl From<Response> for JsResponse {
+    fn from(response: Response) -> JsResponse {
+        match response {
+            Response::State(inner) => JsResponse::State(inner),
+            Response::Settings(inner) => JsResponse::Settings(inner),
+            Response::StoredStats(inner) => JsResponse::StoredStats(inner),
+        }
+    }
+}
+
+/// Parses a notification into an object such as
+/// `{ type: "State", motor_state: "Running", speed: 25, ... }`.
+#[wasm_bindgen(js_name = parseResponse)]
+pub fn parse_response(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let response = Response::parse(bytes).map_err(protocol_error)?;
+
+    JsResponse::from(response)
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|err| JsError::new(&err.to_string()))
+}
@@ -0,0 +1,44 @@
+#![cfg(target_arch = "wasm32")]
+
+use serde::Deserialize;
+use walkingpad_protocol::fixtures::STATE;
+use walkingpad_protocol::request;
+use walkingpad_protocol::response::{MotorState, State};
+use walkingpad_protocol::{Mode, Speed};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[derive(Deserialize)]
+struct Tagged {
+    r#type: String,
+}
+
+#[wasm_bindgen_test]
+fn test_requests() {
+    assert_eq!(walkingpad_wasm::start(), request::start().as_bytes());
+    assert_eq!(
+        walkingpad_wasm::set_speed(25).unwrap(),
+        request::set::speed(Speed::from_hm_per_hour(25)).as_bytes()
+    );
+    assert!(walkingpad_wasm::set_speed(61).is_err());
+    assert_eq!(
+        walkingpad_wasm::set_mode(JsValue::from_str("Manual")).unwrap(),
+        request::set::mode(Mode::Manual).as_bytes()
+    );
+    assert!(walkingpad_wasm::set_mode(JsValue::from_str("Jogging")).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_parse_response() {
+    let parsed = walkingpad_wasm::parse_response(&STATE).unwrap();
+
+    let tagged: Tagged = serde_wasm_bindgen::from_value(parsed.clone()).unwrap();
+    assert_eq!(tagged.r#type, "State");
+
+    let state: State = serde_wasm_bindgen::from_value(parsed).unwrap();
+    assert_eq!(state.motor_state, MotorState::Running);
+    assert_eq!(state.speed, Speed::from_hm_per_hour(25));
+    assert_eq!(state.distance, 500);
+
+    assert!(walkingpad_wasm::parse_response(&STATE[..10]).is_err());
+}